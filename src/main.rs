@@ -3,15 +3,124 @@ extern crate sdl2 as sdl;
 extern crate smallvec;
 extern crate vulkano;
 
+mod vw_device_selection;
 mod vw_engine;
+mod vw_engine2;
+mod vw_error;
+mod vw_math;
+mod vw_queue_family;
+mod vw_renderer;
+mod vw_scene;
 mod vw_window;
 
 fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
     if cfg!(debug_assertions) {
-        println!("Running in debug mode.");
+        log::info!("Running in debug mode.");
+    }
+
+    let args = std::env::args().collect::<Vec<_>>();
+    let headless_surface = args.iter().any(|arg| arg == "--headless-surface");
+    let headless = if headless_surface || args.iter().any(|arg| arg == "--headless") {
+        Some(vw_engine::VwHeadlessCreateInfo {
+            extent: [1280, 720],
+            headless_surface,
+        })
+    } else {
+        None
+    };
+
+    // `--split-present` together with `--exclusive-sharing` covers the ownership-transfer path
+    // of the ash backend on hardware where one queue family does both graphics and presentation
+    let swapchain_sharing = if args.iter().any(|arg| arg == "--exclusive-sharing") {
+        vw_engine::VwSwapchainSharing::Exclusive
+    } else {
+        vw_engine::VwSwapchainSharing::Concurrent
+    };
+
+    // HDR and wide-gamut output are opt-in, they need a display that supports them
+    let color_output = if args.iter().any(|arg| arg == "--hdr10") {
+        vw_engine::VwColorOutput::Hdr10
+    } else if args.iter().any(|arg| arg == "--scrgb") {
+        vw_engine::VwColorOutput::ScRgb
+    } else if args.iter().any(|arg| arg == "--display-p3") {
+        vw_engine::VwColorOutput::DisplayP3
+    } else {
+        vw_engine::VwColorOutput::Sdr
+    };
+
+    // Loaded before the renderer is created, so a broken file fails fast
+    let scene = if let Some(path) = arg_value(&args, "--gltf")? {
+        Some(vw_scene::VwScene::from_gltf(path)?)
+    } else if let Some(path) = arg_value(&args, "--obj")? {
+        let generated_normals = if args.iter().any(|arg| arg == "--flat-normals") {
+            vw_scene::VwGeneratedNormals::Flat
+        } else {
+            vw_scene::VwGeneratedNormals::Smooth
+        };
+        Some(vw_scene::VwScene::from_obj(path, generated_normals)?)
+    } else {
+        None
+    };
+    let headless_extent = headless.as_ref().map(|headless| headless.extent);
+
+    let create_info = vw_engine::VkWizardEngineCreateInfo {
+        headless,
+        color_output,
+        transparent: args.iter().any(|arg| arg == "--transparent"),
+        swapchain_sharing,
+        simulate_split_present_family: args.iter().any(|arg| arg == "--split-present"),
+        ..Default::default()
+    };
+
+    // `info` prints what the engine sees of the Vulkan installation instead of rendering
+    if args.get(1).is_some_and(|arg| arg == "info") {
+        let format = if args.iter().any(|arg| arg == "--json") {
+            vw_engine::VwInfoFormat::Json
+        } else {
+            vw_engine::VwInfoFormat::Text
+        };
+        return vw_engine::VwInfoReport::new(&create_info)?.print(format);
     }
 
-    let _engine = vw_engine::VkWizardEngine::new()?;
+    // The raw ash backend is opt-in and only exists to compare against the vulkano one
+    let backend = if args.iter().any(|arg| arg == "--ash") {
+        vw_renderer::VwBackend::Ash
+    } else {
+        vw_renderer::VwBackend::Vulkano
+    };
 
-    Ok(())
+    let mut renderer = vw_renderer::create_renderer(backend, create_info)?;
+    if let Some(chosen) = renderer.color_output()
+        && chosen != color_output
+    {
+        log::warn!("Asked for {color_output:?} output, presenting {chosen:?} instead");
+    }
+
+    // The scene replaces the built-in triangle and is framed for the initial window size
+    if let Some(scene) = scene {
+        scene.upload(renderer.as_mut())?;
+
+        let [width, height] = renderer
+            .window()
+            .map(vw_window::VwWindow::drawable_extent)
+            .or(headless_extent)
+            .unwrap_or([1, 1]);
+        let aspect_ratio = width.max(1) as f32 / height.max(1) as f32;
+        renderer.set_view_projection(scene.view_projection(aspect_ratio));
+    }
+
+    renderer.run()
+}
+
+/// The argument following `flag`, `None` when the flag isn't given.
+fn arg_value<'a>(args: &'a [String], flag: &str) -> anyhow::Result<Option<&'a str>> {
+    match args.iter().position(|arg| arg == flag) {
+        Some(index) => match args.get(index + 1) {
+            Some(value) => Ok(Some(value)),
+            None => anyhow::bail!("{flag} needs a path"),
+        },
+        None => Ok(None),
+    }
 }
@@ -0,0 +1,377 @@
+use crate::vw_error::VwError;
+
+/// Environment variable that overrides the configured device selection, parsed with
+/// [`VwDeviceSelection::parse`].
+pub const DEVICE_ENV_VAR: &str = "VKWIZARD_DEVICE";
+
+/// How a physical device is picked among the suitable ones. Shared by both backends.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum VwDeviceSelection {
+    /// Prefers discrete GPUs, then the largest maximum image size.
+    #[default]
+    HighPerformance,
+    /// Prefers integrated GPUs, e.g. to save battery on laptops.
+    LowPower,
+    /// Only devices whose name contains this string, ignoring case.
+    Name(String),
+    /// Only devices with this PCI vendor ID, and device ID when given.
+    Id {
+        vendor_id: u32,
+        device_id: Option<u32>,
+    },
+    /// Only the device at this position in the driver's enumeration order.
+    Index(usize),
+}
+
+impl VwDeviceSelection {
+    /// Parses a selection as written in `VKWIZARD_DEVICE`:
+    /// - `high-performance` or `discrete`, `low-power` or `integrated`
+    /// - `#1` or `1` for an index
+    /// - `0x10de` or `0x10de:0x2684` for a vendor and optional device ID
+    /// - anything else is matched against the device name, e.g. `llvmpipe`
+    pub fn parse(selection: &str) -> Self {
+        let selection = selection.trim();
+
+        match selection.to_ascii_lowercase().as_str() {
+            "high-performance" | "discrete" => return VwDeviceSelection::HighPerformance,
+            "low-power" | "integrated" => return VwDeviceSelection::LowPower,
+            _ => {}
+        }
+
+        let index = selection.strip_prefix('#').unwrap_or(selection);
+        if let Ok(index) = index.parse() {
+            return VwDeviceSelection::Index(index);
+        }
+
+        if let Some(id) = parse_id(selection) {
+            return id;
+        }
+
+        VwDeviceSelection::Name(selection.to_string())
+    }
+
+    /// The selection from `VKWIZARD_DEVICE`, if it is set and not empty.
+    pub fn from_env() -> Option<Self> {
+        std::env::var(DEVICE_ENV_VAR)
+            .ok()
+            .filter(|selection| !selection.trim().is_empty())
+            .map(|selection| VwDeviceSelection::parse(&selection))
+    }
+
+    /// The selection to actually use, which is `configured` unless `VKWIZARD_DEVICE` overrides
+    /// it. Both backends and `info` go through this, so they always agree on the device.
+    pub fn resolve(configured: &VwDeviceSelection) -> Self {
+        match VwDeviceSelection::from_env() {
+            Some(selection) => {
+                log::info!("Device selection overridden by the environment: {selection:?}");
+                selection
+            }
+            None => configured.clone(),
+        }
+    }
+
+    /// Whether the device is allowed by the selection at all. The preference-based selections
+    /// allow every device and only affect [`score`](Self::score).
+    pub fn matches(&self, index: usize, name: &str, vendor_id: u32, device_id: u32) -> bool {
+        match self {
+            VwDeviceSelection::HighPerformance | VwDeviceSelection::LowPower => true,
+            VwDeviceSelection::Name(needle) => name
+                .to_ascii_lowercase()
+                .contains(&needle.to_ascii_lowercase()),
+            VwDeviceSelection::Id {
+                vendor_id: selected_vendor_id,
+                device_id: selected_device_id,
+            } => {
+                *selected_vendor_id == vendor_id
+                    && selected_device_id.is_none_or(|selected| selected == device_id)
+            }
+            VwDeviceSelection::Index(selected) => *selected == index,
+        }
+    }
+
+    /// Ranks a suitable device, the highest score wins.
+    pub fn score(&self, discrete: bool, integrated: bool, max_image_dimension: u32) -> u32 {
+        let preferred = match self {
+            VwDeviceSelection::LowPower => integrated,
+            _ => discrete,
+        };
+
+        if preferred {
+            1000 + max_image_dimension
+        } else {
+            max_image_dimension
+        }
+    }
+}
+
+/// The kind of a physical device, the same for both backends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VwDeviceType {
+    DiscreteGpu,
+    IntegratedGpu,
+    VirtualGpu,
+    Cpu,
+    Other,
+}
+
+/// A physical device as seen during selection. `D` is the backend's handle to it.
+#[derive(Clone)]
+pub struct VwDeviceCandidate<D> {
+    /// Position in the driver's enumeration order, as used by [`VwDeviceSelection::Index`].
+    pub index: usize,
+    pub name: String,
+    pub device_type: VwDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub max_image_dimension: u32,
+    /// `None` when the device was rejected.
+    pub score: Option<u32>,
+    /// Why the device can't be used, empty for suitable devices.
+    pub rejection_reasons: Vec<String>,
+    pub physical_device: D,
+}
+
+/// Rejects the candidates `selection` doesn't match and scores the rest. `candidates` come in
+/// enumeration order with the backend's own rejection reasons filled in. Suitable devices end up
+/// first, best score first, followed by the rejected ones in enumeration order.
+pub fn rank_candidates<D>(
+    mut candidates: Vec<VwDeviceCandidate<D>>,
+    selection: &VwDeviceSelection,
+) -> Vec<VwDeviceCandidate<D>> {
+    for candidate in candidates.iter_mut() {
+        if !selection.matches(
+            candidate.index,
+            &candidate.name,
+            candidate.vendor_id,
+            candidate.device_id,
+        ) {
+            candidate
+                .rejection_reasons
+                .push(format!("not matching {selection:?}"));
+        }
+
+        candidate.score = candidate.rejection_reasons.is_empty().then(|| {
+            selection.score(
+                candidate.device_type == VwDeviceType::DiscreteGpu,
+                candidate.device_type == VwDeviceType::IntegratedGpu,
+                candidate.max_image_dimension,
+            )
+        });
+    }
+
+    // Stable sort, so rejected devices and ties keep their enumeration order
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.score));
+
+    candidates
+}
+
+/// Logs every ranked candidate and returns the best one, if it was accepted at all.
+pub fn select_candidate<D>(candidates: &[VwDeviceCandidate<D>]) -> Result<&D, VwError> {
+    for candidate in candidates.iter() {
+        match candidate.score {
+            Some(score) => log::info!(
+                "Vulkan device #{} {} ({:?}): score {score}",
+                candidate.index,
+                candidate.name,
+                candidate.device_type
+            ),
+            None => log::info!(
+                "Vulkan device #{} {} ({:?}): rejected, {}",
+                candidate.index,
+                candidate.name,
+                candidate.device_type,
+                candidate.rejection_reasons.join(", ")
+            ),
+        }
+    }
+
+    // Candidates are ranked, so the first one is the best if it was accepted at all
+    candidates
+        .first()
+        .filter(|candidate| candidate.score.is_some())
+        .map(|candidate| &candidate.physical_device)
+        .ok_or(VwError::NoSuitableDevice)
+}
+
+fn parse_id(selection: &str) -> Option<VwDeviceSelection> {
+    let parse_hex = |id: &str| {
+        let id = id.strip_prefix("0x").or_else(|| id.strip_prefix("0X"))?;
+        u32::from_str_radix(id, 16).ok()
+    };
+
+    let (vendor_id, device_id) = match selection.split_once(':') {
+        Some((vendor_id, device_id)) => (parse_hex(vendor_id)?, Some(parse_hex(device_id)?)),
+        None => (parse_hex(selection)?, None),
+    };
+
+    Some(VwDeviceSelection::Id {
+        vendor_id,
+        device_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(
+        index: usize,
+        name: &str,
+        device_type: VwDeviceType,
+        max_image_dimension: u32,
+    ) -> VwDeviceCandidate<usize> {
+        VwDeviceCandidate {
+            index,
+            name: name.to_string(),
+            device_type,
+            vendor_id: 0x10de,
+            device_id: 0x2684 + index as u32,
+            max_image_dimension,
+            score: None,
+            rejection_reasons: Vec::new(),
+            physical_device: index,
+        }
+    }
+
+    fn ranked_indices(candidates: &[VwDeviceCandidate<usize>]) -> Vec<(usize, bool)> {
+        candidates
+            .iter()
+            .map(|candidate| (candidate.physical_device, candidate.score.is_some()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_preferences() {
+        for selection in ["high-performance", "discrete", " Discrete "] {
+            assert_eq!(
+                VwDeviceSelection::parse(selection),
+                VwDeviceSelection::HighPerformance
+            );
+        }
+        for selection in ["low-power", "INTEGRATED"] {
+            assert_eq!(
+                VwDeviceSelection::parse(selection),
+                VwDeviceSelection::LowPower
+            );
+        }
+    }
+
+    #[test]
+    fn parse_index() {
+        assert_eq!(VwDeviceSelection::parse("1"), VwDeviceSelection::Index(1));
+        assert_eq!(VwDeviceSelection::parse("#2"), VwDeviceSelection::Index(2));
+    }
+
+    #[test]
+    fn parse_id() {
+        assert_eq!(
+            VwDeviceSelection::parse("0x10de"),
+            VwDeviceSelection::Id {
+                vendor_id: 0x10de,
+                device_id: None
+            }
+        );
+        assert_eq!(
+            VwDeviceSelection::parse("0X10DE:0x2684"),
+            VwDeviceSelection::Id {
+                vendor_id: 0x10de,
+                device_id: Some(0x2684)
+            }
+        );
+    }
+
+    #[test]
+    fn parse_name() {
+        assert_eq!(
+            VwDeviceSelection::parse("llvmpipe"),
+            VwDeviceSelection::Name("llvmpipe".to_string())
+        );
+        // Not a valid ID, so it can only be a name
+        assert_eq!(
+            VwDeviceSelection::parse("0x10de:"),
+            VwDeviceSelection::Name("0x10de:".to_string())
+        );
+    }
+
+    #[test]
+    fn rank_prefers_discrete() {
+        let candidates = vec![
+            candidate(0, "Integrated", VwDeviceType::IntegratedGpu, 8192),
+            candidate(1, "Discrete", VwDeviceType::DiscreteGpu, 8192),
+            candidate(2, "Software", VwDeviceType::Cpu, 4096),
+        ];
+
+        let ranked = rank_candidates(candidates, &VwDeviceSelection::HighPerformance);
+        assert_eq!(ranked_indices(&ranked), [(1, true), (0, true), (2, true)]);
+        assert_eq!(ranked[0].score, Some(1000 + 8192));
+    }
+
+    #[test]
+    fn rank_low_power_prefers_integrated() {
+        let candidates = vec![
+            candidate(0, "Discrete", VwDeviceType::DiscreteGpu, 8192),
+            candidate(1, "Integrated", VwDeviceType::IntegratedGpu, 8192),
+        ];
+
+        let ranked = rank_candidates(candidates, &VwDeviceSelection::LowPower);
+        assert_eq!(ranked_indices(&ranked), [(1, true), (0, true)]);
+    }
+
+    #[test]
+    fn rank_ties_by_image_dimension() {
+        let candidates = vec![
+            candidate(0, "Small", VwDeviceType::DiscreteGpu, 8192),
+            candidate(1, "Large", VwDeviceType::DiscreteGpu, 16384),
+            candidate(2, "Same", VwDeviceType::DiscreteGpu, 8192),
+        ];
+
+        let ranked = rank_candidates(candidates, &VwDeviceSelection::HighPerformance);
+        assert_eq!(ranked_indices(&ranked), [(1, true), (0, true), (2, true)]);
+    }
+
+    #[test]
+    fn rank_rejects_unmatched_after_suitable() {
+        let mut candidates = vec![
+            candidate(0, "Discrete", VwDeviceType::DiscreteGpu, 16384),
+            candidate(1, "llvmpipe", VwDeviceType::Cpu, 8192),
+            candidate(2, "Other llvmpipe", VwDeviceType::Cpu, 8192),
+        ];
+        candidates[2]
+            .rejection_reasons
+            .push("missing swapchain".to_string());
+
+        let ranked = rank_candidates(candidates, &VwDeviceSelection::parse("LLVMpipe"));
+        assert_eq!(ranked_indices(&ranked), [(1, true), (0, false), (2, false)]);
+        assert!(ranked[1].rejection_reasons[0].starts_with("not matching"));
+        assert_eq!(ranked[2].rejection_reasons, ["missing swapchain"]);
+        assert_eq!(select_candidate(&ranked).unwrap(), &1);
+    }
+
+    #[test]
+    fn rank_by_index_and_id() {
+        let candidates = || {
+            vec![
+                candidate(0, "First", VwDeviceType::DiscreteGpu, 16384),
+                candidate(1, "Second", VwDeviceType::DiscreteGpu, 16384),
+            ]
+        };
+
+        let ranked = rank_candidates(candidates(), &VwDeviceSelection::Index(1));
+        assert_eq!(ranked_indices(&ranked), [(1, true), (0, false)]);
+
+        let ranked = rank_candidates(candidates(), &VwDeviceSelection::parse("0x10de:0x2684"));
+        assert_eq!(ranked_indices(&ranked), [(0, true), (1, false)]);
+    }
+
+    #[test]
+    fn nothing_suitable() {
+        let ranked = rank_candidates(
+            vec![candidate(0, "Discrete", VwDeviceType::DiscreteGpu, 16384)],
+            &VwDeviceSelection::Index(3),
+        );
+        assert!(matches!(
+            select_candidate(&ranked),
+            Err(VwError::NoSuitableDevice)
+        ));
+    }
+}
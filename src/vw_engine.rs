@@ -1,141 +1,814 @@
 use std::{sync::Arc, vec};
 
-use sdl::{event::Event, keyboard::Scancode};
 use vulkano::{
-    VulkanLibrary,
-    instance::{Instance, InstanceCreateFlags, InstanceCreateInfo, InstanceExtensions},
+    Validated, Version, VulkanError, VulkanLibrary,
+    command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, PrimaryAutoCommandBuffer,
+        RenderingAttachmentInfo, RenderingInfo, allocator::StandardCommandBufferAllocator,
+    },
+    descriptor_set::allocator::StandardDescriptorSetAllocator,
+    device::Queue,
+    format::ClearValue,
+    image::view::ImageView,
+    instance::{
+        Instance, InstanceCreateFlags, InstanceCreateInfo, InstanceExtensions,
+        debug::ValidationFeatureEnable,
+    },
+    pipeline::{
+        PipelineBindPoint,
+        graphics::viewport::{Scissor, Viewport},
+    },
+    render_pass::{AttachmentLoadOp, AttachmentStoreOp},
+    swapchain::{self, Surface, SwapchainPresentInfo},
+    sync::GpuFuture,
 };
 
 use crate::{
-    vw_engine::{vw_device::VwDevice, vw_pipeline::VwPipeline, vw_swapchain::VwSwapchain},
+    vw_device_selection::VwDeviceSelection,
+    vw_engine::{
+        vw_buffer::VwStagingRing,
+        vw_debug::VwDebugMessenger,
+        vw_depth::VwDepthBuffer,
+        vw_device::{VwDevice, VwDeviceCreateInfo},
+        vw_frames::VwFrames,
+        vw_material::VwGpuMaterial,
+        vw_mesh::VwMesh,
+        vw_offscreen::VwOffscreenTarget,
+        vw_pipeline::VwPipeline,
+        vw_swapchain::{VwSwapchain, VwSwapchainCreateInfo},
+    },
+    vw_error::VwError,
+    vw_math::{self, VwMat4},
+    vw_renderer::{VALIDATION_LAYER, VwRenderer, check_extension_support, is_layer_available},
+    vw_scene::VwMaterial,
     vw_window::{VwWindow, VwWindowCreateInfo},
 };
 
+mod vw_buffer;
+mod vw_debug;
+mod vw_depth;
 mod vw_device;
+mod vw_frames;
+mod vw_info;
+mod vw_material;
+mod vw_memory;
+mod vw_mesh;
+mod vw_offscreen;
 mod vw_pipeline;
 mod vw_swapchain;
+mod vw_texture;
+
+pub use vw_buffer::VwBufferUsage;
+pub use vw_debug::{VwDebugLog, VwDebugMessage};
+pub use vw_info::{VwInfoFormat, VwInfoReport};
+pub use vw_memory::{VwHeapStats, log_heap_stats};
+pub use vw_mesh::{VwVertex, VwVertexLayout};
+pub use vw_pipeline::VwPushConstants;
+pub use vw_swapchain::{VwColorOutput, VwPreRotation, VwPresentPolicy, VwSwapchainSharing};
+pub use vw_texture::{VwSamplerCreateInfo, VwTexture, VwTextureCreateInfo, VwTextureData};
+
+/// Size of the staging ring meshes are uploaded through. Larger uploads are split up.
+const STAGING_RING_SIZE: u64 = 16 * 1024 * 1024;
 
 pub struct VkWizardEngine {
     vk_library: Arc<VulkanLibrary>,
     vk_instance: Arc<Instance>,
+    vw_debug: Option<VwDebugMessenger>,
 
     vw_device: VwDevice,
-    vw_swapchain: VwSwapchain,
+    vw_swapchain: Option<VwSwapchain>,
+    vw_offscreen: Option<VwOffscreenTarget>,
+    vw_depth: VwDepthBuffer,
     vw_pipeline: VwPipeline,
-    vw_window: VwWindow,
+    vw_window: Option<VwWindow>,
+
+    vw_vertex_layout: VwVertexLayout,
+    vw_staging: VwStagingRing,
+    vw_textures: Vec<VwTexture>,
+    /// Bound in every texture slot a material leaves empty.
+    vw_white_texture: VwTexture,
+    vw_materials: Vec<Arc<VwGpuMaterial>>,
+    /// Used by meshes added without a material.
+    vw_default_material: Arc<VwGpuMaterial>,
+    vw_meshes: Vec<(VwMesh, Arc<VwGpuMaterial>)>,
+    view_projection: VwMat4,
+
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    vw_frames: VwFrames,
+    recreate_swapchain: bool,
+}
+
+pub struct VkWizardEngineCreateInfo<'s> {
+    pub window: VwWindowCreateInfo<'s>,
+    /// Runs the engine without an SDL window when set, and `window` is ignored.
+    pub headless: Option<VwHeadlessCreateInfo>,
+    pub application_name: &'s str,
+    pub application_version: Version,
+    pub max_api_version: Version,
+    /// Which GPU to render on, `VKWIZARD_DEVICE` takes precedence when set.
+    pub device_selection: VwDeviceSelection,
+    /// Mapped against the present modes the surface supports, can be switched at runtime.
+    pub present_policy: VwPresentPolicy,
+    /// How swapchain images move between the graphics and present queue families.
+    pub swapchain_sharing: VwSwapchainSharing,
+    /// Presents from a separate queue family when the device has one that can, so the
+    /// split-family paths get exercised on hardware that doesn't need them.
+    pub simulate_split_present_family: bool,
+    /// Swapchain images to ask for, clamped to the surface limits.
+    pub swapchain_image_count: u32,
+    /// HDR or wide-gamut output, which the shaders encode for. Falls back to SDR when the surface
+    /// doesn't support it, see `VwRenderer::color_output`.
+    pub color_output: VwColorOutput,
+    /// Renders in the display's native orientation and rotates clip space to match, instead of
+    /// leaving it to the compositor. The rotation is reported through `VwRenderer::pre_rotation`.
+    pub pre_rotation: bool,
+    /// Blends the window with the desktop where the compositor allows it.
+    pub transparent: bool,
+    /// How many frames the CPU may record ahead of the GPU.
+    pub frames_in_flight: usize,
+    pub validation: bool,
+    /// Extra checks such as GPU-assisted, best-practices and synchronization validation.
+    /// Ignored when validation is off or `VK_EXT_validation_features` is unavailable.
+    pub validation_features: &'s [ValidationFeatureEnable],
+    /// Panics as soon as the validation layer reports an error, meant for tests.
+    pub panic_on_validation_error: bool,
+    /// SPIR-V containing the `vertMain` and `fragMain` entry points.
+    pub shader_code: &'s [u8],
+}
+
+pub struct VwHeadlessCreateInfo {
+    pub extent: [u32; 2],
+    /// Render through a swapchain on a `VK_EXT_headless_surface` instead of an offscreen image.
+    pub headless_surface: bool,
 }
 
 impl VkWizardEngine {
-    pub fn new() -> anyhow::Result<Self> {
+    /// Creates the engine. With `create_info.headless` set no SDL window is opened, which is
+    /// meant for CI and render-farm machines running on software drivers such as lavapipe.
+    pub fn new(create_info: VkWizardEngineCreateInfo) -> anyhow::Result<Self> {
+        let vw_window = match create_info.headless {
+            Some(_) => None,
+            None => Some(VwWindow::new(create_info.window.clone())?),
+        };
+
+        let surface_extensions = match (&vw_window, &create_info.headless) {
+            (Some(vw_window), _) => vw_window.required_instance_extensions()?,
+            (None, Some(headless)) if headless.headless_surface => vec!["VK_EXT_headless_surface"],
+            (None, _) => vec![],
+        };
+
         let vk_library = VulkanLibrary::new()?;
-        let vk_instance = create_vulkan_instance(vk_library.clone())?;
+        let validation = create_info.validation && is_validation_layer_available(&vk_library);
+        let mut vw_debug = (validation && vk_library.supported_extensions().ext_debug_utils)
+            .then(|| VwDebugMessenger::new(create_info.panic_on_validation_error));
+        if validation && vw_debug.is_none() {
+            log::warn!("VK_EXT_debug_utils is not supported, validation messages won't be logged");
+        }
+
+        let vk_instance = create_vulkan_instance(
+            vk_library.clone(),
+            &surface_extensions,
+            &create_info,
+            validation,
+            vw_debug.as_ref(),
+        )?;
+        if let Some(vw_debug) = &mut vw_debug {
+            vw_debug.attach(vk_instance.clone())?;
+        }
+
+        let device_create_info = VwDeviceCreateInfo {
+            selection: create_info.device_selection.clone(),
+            simulate_split_present_family: create_info.simulate_split_present_family,
+            ..Default::default()
+        };
+        let swapchain_create_info = VwSwapchainCreateInfo {
+            present_policy: create_info.present_policy,
+            image_count: create_info.swapchain_image_count,
+            frames_in_flight: create_info.frames_in_flight,
+            sharing: create_info.swapchain_sharing,
+            color_output: create_info.color_output,
+            pre_rotation: create_info.pre_rotation,
+            transparent: create_info.transparent,
+        };
+
+        let (vw_device, vw_swapchain, vw_offscreen) = match (&vw_window, &create_info.headless) {
+            (Some(vw_window), _) => {
+                let surface = vw_window.create_vk_surface(vk_instance.clone())?;
+                let vw_device =
+                    VwDevice::new(vk_instance.clone(), Some(surface), &device_create_info)?;
+                let vw_swapchain = VwSwapchain::new(
+                    &vw_device,
+                    vw_window.drawable_extent(),
+                    &swapchain_create_info,
+                )?;
+                (vw_device, Some(vw_swapchain), None)
+            }
+            (None, Some(headless)) if headless.headless_surface => {
+                let surface = Surface::headless(vk_instance.clone(), None)?;
+                let vw_device =
+                    VwDevice::new(vk_instance.clone(), Some(surface), &device_create_info)?;
+                let vw_swapchain =
+                    VwSwapchain::new(&vw_device, headless.extent, &swapchain_create_info)?;
+                (vw_device, Some(vw_swapchain), None)
+            }
+            (None, headless) => {
+                let extent = headless
+                    .as_ref()
+                    .map_or([1280, 720], |headless| headless.extent);
+                let vw_device = VwDevice::new(vk_instance.clone(), None, &device_create_info)?;
+                let vw_offscreen = VwOffscreenTarget::new(&vw_device, extent)?;
+                (vw_device, None, Some(vw_offscreen))
+            }
+        };
+
+        let (color_format, extent) = match (&vw_swapchain, &vw_offscreen) {
+            (Some(vw_swapchain), _) => (vw_swapchain.surface_format(), vw_swapchain.extent()),
+            (None, Some(vw_offscreen)) => (vw_offscreen.format(), vw_offscreen.extent()),
+            (None, None) => unreachable!("The engine always has a render target"),
+        };
+        let vw_depth = VwDepthBuffer::new(&vw_device, extent)?;
+
+        let vw_vertex_layout = VwVertexLayout::default();
+        let vw_pipeline = VwPipeline::new(
+            &vw_device,
+            create_info.shader_code,
+            color_format,
+            vw_depth.format(),
+            &vw_vertex_layout,
+        )?;
 
-        let vw_window = VwWindow::new(VwWindowCreateInfo {
-            title: "VkWizard Window",
-            position: (-1400, 100),
+        let queue_family = |queue: Option<Arc<Queue>>| {
+            queue.map_or("none".to_string(), |queue| {
+                queue.queue_family_index().to_string()
+            })
+        };
+        log::info!(
+            "Dedicated transfer queue family: {}, dedicated compute queue family: {}",
+            queue_family(vw_device.transfer_queue()),
+            queue_family(vw_device.compute_queue())
+        );
+
+        let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
+            vw_device.logical_device(),
+            Default::default(),
+        ));
+
+        let mut vw_staging = VwStagingRing::new(
+            &vw_device,
+            command_buffer_allocator.clone(),
+            STAGING_RING_SIZE,
+        )?;
+        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+            vw_device.logical_device(),
+            Default::default(),
+        ));
+
+        let vw_white_texture = VwTexture::new(
+            &vw_device,
+            command_buffer_allocator.clone(),
+            &VwTextureData::rgba8(1, 1, vec![255; 4]),
+            &VwTextureCreateInfo {
+                generate_mipmaps: false,
+                ..Default::default()
+            },
+        )?;
+        let create_material = |material: &VwMaterial| {
+            VwGpuMaterial::new(
+                &vw_device,
+                descriptor_set_allocator.clone(),
+                vw_pipeline.material_set_layout(),
+                material,
+                &[],
+                &vw_white_texture,
+            )
+            .map(Arc::new)
+        };
+        let vw_default_material = create_material(&VwMaterial::default())?;
+        // The triangle's winding is clockwise on screen, so it is drawn double-sided. Diffuse
+        // so its vertex colors show
+        let triangle_material = create_material(&VwMaterial {
+            metallic_factor: 0.0,
+            double_sided: true,
             ..Default::default()
-        });
+        })?;
 
-        let surface = vw_window.create_vk_surface(vk_instance.clone());
+        let vw_meshes = vec![(
+            VwMesh::fullscreen_triangle(&vw_device, &mut vw_staging, &vw_vertex_layout)?,
+            triangle_material,
+        )];
+        vw_staging.flush()?;
 
-        let vw_device = VwDevice::new(vk_instance.clone(), surface)?;
-        let vw_swapchain = VwSwapchain::new(&vw_device)?;
+        let vw_frames = match &vw_swapchain {
+            Some(vw_swapchain) => {
+                log::info!(
+                    "Swapchain has {} images, asked for at least {}, {} frames in flight",
+                    vw_swapchain.image_count(),
+                    vw_swapchain.min_image_count(),
+                    vw_swapchain.frames_in_flight()
+                );
+                VwFrames::new(vw_swapchain.frames_in_flight())
+            }
+            None => VwFrames::new(create_info.frames_in_flight),
+        };
 
-        let shader_code = include_bytes!("../shaders/shader.spv");
-        let vw_pipeline = VwPipeline::new(&vw_device, shader_code)?;
+        if let Some(vw_debug) = &vw_debug {
+            vw_debug.check();
+        }
 
         Ok(VkWizardEngine {
             vk_library,
             vk_instance,
+            vw_debug,
 
             vw_device,
             vw_swapchain,
+            vw_offscreen,
+            vw_depth,
             vw_pipeline,
             vw_window,
+
+            vw_vertex_layout,
+            vw_staging,
+            vw_textures: vec![],
+            vw_white_texture,
+            vw_materials: vec![],
+            vw_default_material,
+            vw_meshes,
+            view_projection: vw_math::IDENTITY,
+
+            command_buffer_allocator,
+            descriptor_set_allocator,
+            vw_frames,
+            recreate_swapchain: false,
         })
     }
 
-    pub fn run(&self) {
-        let mut event_pump = self.vw_window.event_pump();
-        loop {
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. } => return,
-                    Event::KeyDown {
-                        scancode: Some(Scancode::Escape),
-                        ..
-                    } => return,
-                    _ => {}
+    /// Uploads a texture on the engine's device, blocking until it is ready to be sampled.
+    pub fn create_texture(
+        &self,
+        data: &VwTextureData,
+        create_info: &VwTextureCreateInfo,
+    ) -> Result<VwTexture, VwError> {
+        VwTexture::new(
+            &self.vw_device,
+            self.command_buffer_allocator.clone(),
+            data,
+            create_info,
+        )
+    }
+
+    /// Rebuilds the swapchain at the window's current drawable size. Returns `false` while the
+    /// window has no drawable area, in which case the swapchain is kept as is.
+    fn recreate_swapchain(&mut self) -> anyhow::Result<bool> {
+        let Some(vw_swapchain) = &mut self.vw_swapchain else {
+            self.recreate_swapchain = false;
+            return Ok(true);
+        };
+
+        // A headless surface has no window, so it keeps its current size
+        let drawable_extent = match &self.vw_window {
+            Some(vw_window) => vw_window.drawable_extent(),
+            None => vw_swapchain.extent(),
+        };
+
+        if drawable_extent.contains(&0) {
+            return Ok(false);
+        }
+
+        // The old images may still be in use by frames in flight
+        self.vw_frames.wait_all()?;
+        if !vw_swapchain.recreate(&self.vw_device, drawable_extent)? {
+            return Ok(false);
+        }
+        self.vw_depth
+            .recreate(&self.vw_device, vw_swapchain.extent())?;
+
+        self.recreate_swapchain = false;
+        Ok(true)
+    }
+
+    fn record_commands(
+        &self,
+        image_view: Arc<ImageView>,
+        extent: [u32; 2],
+    ) -> anyhow::Result<Arc<PrimaryAutoCommandBuffer>> {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            self.vw_device.graphics_queue().queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        let viewport = Viewport {
+            offset: [0.0, 0.0],
+            extent: [extent[0] as f32, extent[1] as f32],
+            depth_range: 0.0..=1.0,
+        };
+        let scissor = Scissor {
+            offset: [0, 0],
+            extent,
+        };
+
+        // Read every frame, the transform can change whenever the swapchain is recreated
+        let view_projection = vw_math::mul(
+            &vw_math::rotate_z(self.pre_rotation().degrees()),
+            &self.view_projection,
+        );
+        let (color_output, color_format) = match (&self.vw_swapchain, &self.vw_offscreen) {
+            (Some(vw_swapchain), _) => (vw_swapchain.color_output(), vw_swapchain.surface_format()),
+            (None, Some(vw_offscreen)) => (VwColorOutput::Sdr, vw_offscreen.format()),
+            (None, None) => unreachable!("The engine always has a render target"),
+        };
+        let push_constants = VwPushConstants {
+            view_projection,
+            eye: vw_math::eye(&self.view_projection),
+            color_output: color_output.shader_id(),
+            encode_srgb: color_output.needs_srgb_encoding(color_format).into(),
+        };
+
+        // Transparent windows show the desktop wherever nothing was drawn
+        let clear_alpha = match &self.vw_swapchain {
+            Some(vw_swapchain) if vw_swapchain.is_transparent() => 0.0,
+            _ => 1.0,
+        };
+
+        builder
+            .begin_rendering(RenderingInfo {
+                render_area_extent: extent,
+                color_attachments: vec![Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    store_op: AttachmentStoreOp::Store,
+                    clear_value: Some([0.0, 0.0, 0.0, clear_alpha].into()),
+                    ..RenderingAttachmentInfo::image_view(image_view)
+                })],
+                depth_attachment: Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    // Only needed while the frame is being drawn
+                    store_op: AttachmentStoreOp::DontCare,
+                    clear_value: Some(ClearValue::Depth(1.0)),
+                    ..RenderingAttachmentInfo::image_view(self.vw_depth.image_view())
+                }),
+                ..Default::default()
+            })?
+            .set_viewport(0, [viewport].into_iter().collect())?
+            .set_scissor(0, [scissor].into_iter().collect())?
+            .bind_pipeline_graphics(self.vw_pipeline.pipeline())?
+            .push_constants(self.vw_pipeline.layout(), 0, push_constants)?;
+
+        // Blended meshes go last so they blend over everything opaque. They are drawn in the
+        // order they were added rather than back to front, which is only right while they don't
+        // overlap each other
+        let (blended, opaque): (Vec<_>, Vec<_>) = self
+            .vw_meshes
+            .iter()
+            .partition(|(_, material)| material.is_blended());
+        for (vw_mesh, material) in opaque {
+            self.draw_mesh(&mut builder, vw_mesh, material)?;
+        }
+        if !blended.is_empty() {
+            builder
+                .bind_pipeline_graphics(self.vw_pipeline.blend_pipeline())?
+                .push_constants(self.vw_pipeline.layout(), 0, push_constants)?;
+            for (vw_mesh, material) in blended {
+                self.draw_mesh(&mut builder, vw_mesh, material)?;
+            }
+        }
+
+        builder.end_rendering()?;
+
+        Ok(builder.build()?)
+    }
+
+    fn draw_mesh(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vw_mesh: &VwMesh,
+        material: &VwGpuMaterial,
+    ) -> anyhow::Result<()> {
+        builder
+            .set_cull_mode(material.cull_mode())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.vw_pipeline.layout(),
+                0,
+                material.descriptor_set(),
+            )?;
+        vw_mesh.draw(builder)?;
+        Ok(())
+    }
+}
+
+impl VwRenderer for VkWizardEngine {
+    fn window(&self) -> Option<&VwWindow> {
+        self.vw_window.as_ref()
+    }
+
+    /// Renders one frame into the swapchain, or into the offscreen target when headless.
+    fn draw_frame(&mut self) -> anyhow::Result<()> {
+        if self.recreate_swapchain && !self.recreate_swapchain()? {
+            // The surface has no area right now, skip rendering until it does
+            return Ok(());
+        }
+
+        let previous_future = self.vw_frames.begin(self.vw_device.logical_device())?;
+        let graphics_queue = self.vw_device.graphics_queue();
+
+        let future = if let Some(vw_swapchain) = &self.vw_swapchain {
+            let acquired = swapchain::acquire_next_image(vw_swapchain.swapchain(), None)
+                .map_err(Validated::unwrap);
+            let (image_index, suboptimal, acquire_future) = match acquired {
+                Ok(acquired) => acquired,
+                Err(VulkanError::OutOfDate) => {
+                    self.recreate_swapchain = true;
+                    return Ok(());
                 }
+                Err(err) => return Err(err.into()),
+            };
+
+            // A suboptimal image can still be presented, but the swapchain should be rebuilt
+            if suboptimal {
+                self.recreate_swapchain = true;
+            }
+
+            let image_view = vw_swapchain.image_views()[image_index as usize].clone();
+            let command_buffer = self.record_commands(image_view, vw_swapchain.extent())?;
+
+            let present_queue = self
+                .vw_device
+                .present_queue()
+                .expect("A swapchain is only created with a present queue");
+            let present_info =
+                SwapchainPresentInfo::swapchain_image_index(vw_swapchain.swapchain(), image_index);
+
+            previous_future
+                .join(acquire_future)
+                .then_execute(graphics_queue, command_buffer)?
+                .then_swapchain_present(present_queue, present_info)
+                .boxed_send_sync()
+        } else if let Some(vw_offscreen) = &self.vw_offscreen {
+            let command_buffer =
+                self.record_commands(vw_offscreen.image_view(), vw_offscreen.extent())?;
+
+            previous_future
+                .then_execute(graphics_queue, command_buffer)?
+                .boxed_send_sync()
+        } else {
+            unreachable!("The engine always has a render target");
+        };
+
+        match future
+            .then_signal_fence_and_flush()
+            .map_err(Validated::unwrap)
+        {
+            Ok(fence) => self.vw_frames.end(Some(Arc::new(fence))),
+            Err(VulkanError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                self.vw_frames.end(None);
             }
-            self.vw_window.present();
+            Err(err) => return Err(err.into()),
+        }
+
+        if let Some(vw_debug) = &self.vw_debug {
+            vw_debug.check();
+        }
+
+        Ok(())
+    }
+
+    fn add_texture(
+        &mut self,
+        data: &VwTextureData,
+        create_info: &VwTextureCreateInfo,
+    ) -> anyhow::Result<usize> {
+        let vw_texture = self.create_texture(data, create_info)?;
+        log::debug!(
+            "Uploaded a {}x{} {:?} texture with {} mip levels",
+            data.width,
+            data.height,
+            data.format,
+            vw_texture.mip_levels()
+        );
+        self.vw_textures.push(vw_texture);
+        Ok(self.vw_textures.len() - 1)
+    }
+
+    fn add_material(&mut self, material: &VwMaterial) -> anyhow::Result<usize> {
+        let vw_material = VwGpuMaterial::new(
+            &self.vw_device,
+            self.descriptor_set_allocator.clone(),
+            self.vw_pipeline.material_set_layout(),
+            material,
+            &self.vw_textures,
+            &self.vw_white_texture,
+        )?;
+        self.vw_materials.push(Arc::new(vw_material));
+        Ok(self.vw_materials.len() - 1)
+    }
+
+    fn add_mesh(
+        &mut self,
+        vertices: &[VwVertex],
+        indices: &[u32],
+        material: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let material = match material {
+            Some(index) => self.vw_materials.get(index).cloned().ok_or_else(|| {
+                VwError::InvalidMesh(format!(
+                    "material {index} was never added, there are {}",
+                    self.vw_materials.len()
+                ))
+            })?,
+            None => self.vw_default_material.clone(),
+        };
+
+        let vw_mesh = VwMesh::new(
+            &self.vw_device,
+            &mut self.vw_staging,
+            &self.vw_vertex_layout,
+            vertices,
+            indices,
+        )?;
+        self.vw_staging.flush()?;
+        self.vw_meshes.push((vw_mesh, material));
+        Ok(())
+    }
+
+    /// Command buffers in flight keep the buffers, descriptor sets and images alive, so there is
+    /// nothing to wait for.
+    fn clear_scene(&mut self) -> anyhow::Result<()> {
+        self.vw_meshes.clear();
+        self.vw_materials.clear();
+        self.vw_textures.clear();
+        Ok(())
+    }
+
+    fn set_view_projection(&mut self, view_projection: VwMat4) {
+        self.view_projection = view_projection;
+    }
+
+    fn request_swapchain_recreate(&mut self) {
+        self.recreate_swapchain = true;
+    }
+
+    fn present_policy(&self) -> Option<VwPresentPolicy> {
+        self.vw_swapchain.as_ref().map(VwSwapchain::present_policy)
+    }
+
+    fn set_present_policy(&mut self, present_policy: VwPresentPolicy) {
+        if let Some(vw_swapchain) = &mut self.vw_swapchain {
+            vw_swapchain.set_present_policy(present_policy);
+            self.recreate_swapchain = true;
         }
     }
+
+    fn color_output(&self) -> Option<VwColorOutput> {
+        self.vw_swapchain.as_ref().map(VwSwapchain::color_output)
+    }
+
+    fn pre_rotation(&self) -> VwPreRotation {
+        self.vw_swapchain
+            .as_ref()
+            .map_or(VwPreRotation::Identity, VwSwapchain::pre_rotation)
+    }
+
+    fn heap_stats(&self) -> Vec<VwHeapStats> {
+        self.vw_device.memory_allocator().heap_stats()
+    }
+
+    fn wait_idle(&mut self) -> anyhow::Result<()> {
+        self.vw_frames.wait_all()
+    }
 }
 
-fn create_vulkan_instance(vk_lib: Arc<VulkanLibrary>) -> anyhow::Result<Arc<Instance>> {
-    let supported_extensions = vk_lib.supported_extensions();
-    if !supported_extensions.khr_surface {
-        list_supported_extensions(&vk_lib);
-        return Err(anyhow::anyhow!(
-            "Required extension khr_surface is not supported"
-        ));
+fn create_vulkan_instance(
+    vk_lib: Arc<VulkanLibrary>,
+    surface_extensions: &[&str],
+    create_info: &VkWizardEngineCreateInfo,
+    validation: bool,
+    vw_debug: Option<&VwDebugMessenger>,
+) -> Result<Arc<Instance>, VwError> {
+    // Layers can provide instance extensions of their own, VK_EXT_validation_features among them
+    let supported_extensions = if validation {
+        vk_lib.supported_extensions_with_layers([VALIDATION_LAYER])?
+    } else {
+        *vk_lib.supported_extensions()
+    };
+
+    let mut enabled_validation_features = vec![];
+    if validation && !create_info.validation_features.is_empty() {
+        if supported_extensions.ext_validation_features {
+            enabled_validation_features = create_info.validation_features.to_vec();
+        } else {
+            log::warn!("VK_EXT_validation_features is not supported, ignoring validation features");
+        }
     }
-    if !supported_extensions.khr_win32_surface {
-        list_supported_extensions(&vk_lib);
-        return Err(anyhow::anyhow!(
-            "Required extension khr_win32_surface is not supported"
-        ));
+
+    let swapchain_colorspace =
+        !surface_extensions.is_empty() && create_info.color_output.needs_colorspace_extension();
+    if swapchain_colorspace && !supported_extensions.ext_swapchain_colorspace {
+        log::warn!(
+            "VK_EXT_swapchain_colorspace is not supported, HDR and wide-gamut output are off"
+        );
     }
 
+    // The surface extensions depend on the windowing system, so SDL tells us which ones it needs.
+    // Offscreen rendering passes none and needs no surface support at all.
     let enabled_extensions = InstanceExtensions {
-        khr_surface: true,
-        khr_win32_surface: true,
-        ..InstanceExtensions::empty()
+        khr_surface: !surface_extensions.is_empty(),
+        ext_swapchain_colorspace: swapchain_colorspace
+            && supported_extensions.ext_swapchain_colorspace,
+        ext_debug_utils: vw_debug.is_some(),
+        ext_validation_features: !enabled_validation_features.is_empty(),
+        ..InstanceExtensions::from_iter(surface_extensions.iter().copied())
     };
 
+    check_extension_support(
+        supported_extensions
+            .into_iter()
+            .filter_map(|(name, supported)| supported.then_some(name)),
+        &enabled_extensions
+            .into_iter()
+            .filter_map(|(name, enabled)| enabled.then_some(name))
+            .collect::<Vec<_>>(),
+    )?;
+
     let mut enabled_layers: Vec<String> = vec![];
-    if cfg!(debug_assertions) {
-        enabled_layers.push("VK_LAYER_KHRONOS_validation".into());
+    if validation {
+        enabled_layers.push(VALIDATION_LAYER.into());
     }
 
     let instance_create_info = InstanceCreateInfo {
         flags: InstanceCreateFlags::empty(),
-        application_name: Some("VkWizard Game".into()),
-        application_version: vulkano::Version {
-            major: 1,
-            minor: 0,
-            patch: 0,
-        },
+        application_name: Some(create_info.application_name.into()),
+        application_version: create_info.application_version,
         engine_name: Some("VkWizard Engine".into()),
         engine_version: vulkano::Version {
             major: 1,
             minor: 0,
             patch: 0,
         },
-        max_api_version: Some(vulkano::Version::V1_4),
+        max_api_version: Some(create_info.max_api_version),
         enabled_layers,
         enabled_extensions,
-        debug_utils_messengers: vec![],
-        enabled_validation_features: vec![],
+        debug_utils_messengers: vw_debug
+            .map(VwDebugMessenger::create_info)
+            .into_iter()
+            .collect(),
+        enabled_validation_features,
         disabled_validation_features: vec![],
 
         ..Default::default()
     };
 
-    if cfg!(debug_assertions) {
-        println!("Attaching validation layers, please wait...");
+    if validation {
+        log::info!("Attaching validation layers, please wait...");
     }
-    let vk_instance = Instance::new(vk_lib, instance_create_info).unwrap();
+    let vk_instance = Instance::new(vk_lib, instance_create_info)?;
 
     Ok(vk_instance)
 }
 
-fn list_supported_extensions(vk_lib: &VulkanLibrary) {
-    println!("Supported extensions:");
-    for ext in vk_lib.supported_extensions().into_iter() {
-        if let (ext, true) = ext {
-            println!("\t{}", ext);
+fn is_validation_layer_available(vk_lib: &VulkanLibrary) -> bool {
+    match vk_lib.layer_properties() {
+        Ok(layers) => {
+            let layers = layers.collect::<Vec<_>>();
+            is_layer_available(layers.iter().map(|layer| layer.name()), VALIDATION_LAYER)
+        }
+        Err(err) => {
+            log::warn!("Failed to enumerate instance layers: {err}");
+            false
+        }
+    }
+}
+
+impl Default for VkWizardEngineCreateInfo<'_> {
+    fn default() -> Self {
+        VkWizardEngineCreateInfo {
+            window: VwWindowCreateInfo {
+                title: "VkWizard Window",
+                ..Default::default()
+            },
+            headless: None,
+            application_name: "VkWizard Game",
+            application_version: Version {
+                major: 1,
+                minor: 0,
+                patch: 0,
+            },
+            max_api_version: Version::V1_4,
+            device_selection: VwDeviceSelection::HighPerformance,
+            present_policy: VwPresentPolicy::LowLatency,
+            swapchain_sharing: VwSwapchainSharing::Concurrent,
+            simulate_split_present_family: false,
+            swapchain_image_count: 3,
+            color_output: VwColorOutput::Sdr,
+            pre_rotation: false,
+            transparent: false,
+            frames_in_flight: 2,
+            validation: cfg!(debug_assertions),
+            validation_features: &[],
+            panic_on_validation_error: false,
+            shader_code: include_bytes!("../shaders/shader.spv"),
         }
     }
 }
@@ -0,0 +1,378 @@
+use std::sync::Arc;
+
+use ash::vk;
+use vulkano::{
+    DeviceSize, VulkanError, VulkanObject,
+    buffer::{Buffer, BufferCreateInfo, BufferMemory, BufferUsage, Subbuffer},
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage,
+        CopyBufferInfo, RecordingCommandBuffer, allocator::StandardCommandBufferAllocator,
+    },
+    device::Queue,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    sync::{
+        AccessFlags, BufferMemoryBarrier, DependencyInfo, PipelineStages,
+        QueueFamilyOwnershipTransfer, fence::Fence, semaphore::Semaphore,
+    },
+};
+
+use crate::{
+    vw_engine::{vw_device::VwDevice, vw_memory::VwMemoryAllocator},
+    vw_error::VwError,
+};
+
+/// What a buffer is for, which decides its usage flags and where its memory lives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VwBufferUsage {
+    /// Device local, filled through a [`VwStagingRing`].
+    Vertex,
+    /// Device local, filled through a [`VwStagingRing`].
+    Index,
+    /// Host visible and persistently mapped, for data rewritten every frame. Prefers memory
+    /// that is also device local where the device has it.
+    Uniform,
+    /// Device local, filled through a [`VwStagingRing`] and readable back with a copy.
+    Storage,
+    /// Host visible and persistently mapped, the source of uploads.
+    Staging,
+}
+
+impl VwBufferUsage {
+    pub fn buffer_usage(self) -> BufferUsage {
+        match self {
+            VwBufferUsage::Vertex => BufferUsage::VERTEX_BUFFER | BufferUsage::TRANSFER_DST,
+            VwBufferUsage::Index => BufferUsage::INDEX_BUFFER | BufferUsage::TRANSFER_DST,
+            VwBufferUsage::Uniform => BufferUsage::UNIFORM_BUFFER,
+            VwBufferUsage::Storage => {
+                BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST | BufferUsage::TRANSFER_SRC
+            }
+            VwBufferUsage::Staging => BufferUsage::TRANSFER_SRC,
+        }
+    }
+
+    pub fn memory_type_filter(self) -> MemoryTypeFilter {
+        match self {
+            VwBufferUsage::Vertex | VwBufferUsage::Index | VwBufferUsage::Storage => {
+                MemoryTypeFilter::PREFER_DEVICE
+            }
+            VwBufferUsage::Uniform => {
+                MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE
+            }
+            VwBufferUsage::Staging => {
+                MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE
+            }
+        }
+    }
+}
+
+/// A buffer allocated from the device's shared memory allocator.
+pub struct VwBuffer {
+    subbuffer: Subbuffer<[u8]>,
+    heap_index: u32,
+    memory_allocator: Arc<VwMemoryAllocator>,
+}
+
+impl VwBuffer {
+    pub fn new(device: &VwDevice, usage: VwBufferUsage, size: DeviceSize) -> Result<Self, VwError> {
+        let memory_allocator = device.memory_allocator();
+
+        // Buffers are exclusive to one queue family at a time. Uploads on a dedicated transfer
+        // queue hand them over to graphics, see `VwStagingRing::flush`, anything else using them
+        // on another family has to transfer ownership the same way
+        let subbuffer = Buffer::new_slice::<u8>(
+            memory_allocator.allocator(),
+            BufferCreateInfo {
+                usage: usage.buffer_usage(),
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: usage.memory_type_filter(),
+                ..Default::default()
+            },
+            size,
+        )?;
+
+        let heap_index = match subbuffer.buffer().memory() {
+            BufferMemory::Normal(memory) => {
+                memory_allocator.heap_index(memory.device_memory().memory_type_index())
+            }
+            _ => unreachable!("Buffers are always bound to allocated memory"),
+        };
+        memory_allocator.record_allocation(heap_index, size);
+
+        Ok(VwBuffer {
+            subbuffer,
+            heap_index,
+            memory_allocator,
+        })
+    }
+
+    /// Creates a host-visible buffer and fills it with `data`.
+    pub fn from_data(
+        device: &VwDevice,
+        usage: VwBufferUsage,
+        data: &[u8],
+    ) -> Result<Self, VwError> {
+        let buffer = VwBuffer::new(device, usage, data.len() as DeviceSize)?;
+        buffer.write(0, data)?;
+        Ok(buffer)
+    }
+
+    /// Copies `data` into the buffer at `offset` through the persistent mapping. Fails for
+    /// device-local buffers and while the GPU is still reading the range.
+    pub fn write(&self, offset: DeviceSize, data: &[u8]) -> Result<(), VwError> {
+        let range = self
+            .subbuffer
+            .clone()
+            .slice(offset..offset + data.len() as DeviceSize);
+        range.write()?.copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn subbuffer(&self) -> Subbuffer<[u8]> {
+        self.subbuffer.clone()
+    }
+
+    pub fn size(&self) -> DeviceSize {
+        self.subbuffer.size()
+    }
+}
+
+impl Drop for VwBuffer {
+    fn drop(&mut self) {
+        self.memory_allocator
+            .record_free(self.heap_index, self.subbuffer.size());
+    }
+}
+
+/// Uploads data to device-local buffers through a fixed-size staging buffer. Copies are batched
+/// into one command buffer and submitted on [`flush`](Self::flush), or earlier when the ring runs
+/// full. Submissions are waited on, so the ring is meant for loading, not per-frame streaming.
+///
+/// Command buffers are recorded and submitted without vulkano's automatic synchronization,
+/// which can't express queue family ownership transfers. Waiting on every submission keeps that
+/// sound: nothing else touches the buffers while a copy is in flight.
+pub struct VwStagingRing {
+    staging: VwBuffer,
+    head: DeviceSize,
+    /// Where the copies run, the dedicated transfer queue when the device has one.
+    queue: Arc<Queue>,
+    /// Where the uploaded buffers are used. When it is of another family than `queue`, ownership
+    /// of every written range is released to it after the copies.
+    graphics_queue: Arc<Queue>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    recording: Option<RecordingCommandBuffer>,
+    /// The ranges copied into since the last flush.
+    written: Vec<Subbuffer<[u8]>>,
+}
+
+impl VwStagingRing {
+    /// Creates a ring of `size` bytes. Uploads go through the dedicated transfer queue when the
+    /// device has one.
+    pub fn new(
+        device: &VwDevice,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        size: DeviceSize,
+    ) -> Result<Self, VwError> {
+        Ok(VwStagingRing {
+            staging: VwBuffer::new(device, VwBufferUsage::Staging, size)?,
+            head: 0,
+            queue: device
+                .transfer_queue()
+                .unwrap_or_else(|| device.graphics_queue()),
+            graphics_queue: device.graphics_queue(),
+            command_buffer_allocator,
+            recording: None,
+            written: vec![],
+        })
+    }
+
+    /// Records a copy of `data` into `dst` at `dst_offset`. Data larger than the ring is split
+    /// into several copies.
+    pub fn upload(
+        &mut self,
+        dst: &VwBuffer,
+        dst_offset: DeviceSize,
+        data: &[u8],
+    ) -> Result<(), VwError> {
+        let capacity = self.staging.size();
+        let mut uploaded = 0;
+
+        while uploaded < data.len() {
+            if self.head == capacity {
+                self.flush()?;
+            }
+
+            let chunk_size = (data.len() - uploaded).min((capacity - self.head) as usize);
+            let chunk = &data[uploaded..uploaded + chunk_size];
+            self.staging.write(self.head, chunk)?;
+
+            let src = self
+                .staging
+                .subbuffer()
+                .slice(self.head..self.head + chunk_size as DeviceSize);
+            let dst_start = dst_offset + uploaded as DeviceSize;
+            let dst = dst
+                .subbuffer()
+                .slice(dst_start..dst_start + chunk_size as DeviceSize);
+            // The range of the ring was waited on before it was reused, and `dst` isn't used by
+            // the GPU until the copy has been flushed
+            if self.recording.is_none() {
+                self.recording = Some(self.begin(self.queue.queue_family_index())?);
+            }
+            unsafe {
+                self.recording
+                    .as_mut()
+                    .unwrap()
+                    .copy_buffer(&CopyBufferInfo::buffers(src, dst.clone()))?;
+            }
+            self.written.push(dst);
+
+            self.head += chunk_size as DeviceSize;
+            uploaded += chunk_size;
+        }
+
+        Ok(())
+    }
+
+    /// Submits the recorded copies and waits for them, after which the destination buffers can
+    /// be used on the graphics queue and the whole ring is free again.
+    pub fn flush(&mut self) -> Result<(), VwError> {
+        let Some(mut copies) = self.recording.take() else {
+            self.head = 0;
+            return Ok(());
+        };
+        let written = std::mem::take(&mut self.written);
+
+        let device = self.queue.device().clone();
+        let fence = Fence::new(device.clone(), Default::default())?;
+        let src_index = self.queue.queue_family_index();
+        let dst_index = self.graphics_queue.queue_family_index();
+
+        if src_index == dst_index {
+            let copies = unsafe { copies.end() }?;
+            submit(&self.queue, &copies, None, None, Some(&fence))?;
+        } else {
+            // The release half of the transfer, after the copies on the transfer queue, and the
+            // acquire half on the graphics queue, which waits for the first through a semaphore
+            let transfer = QueueFamilyOwnershipTransfer::ExclusiveBetweenLocal {
+                src_index,
+                dst_index,
+            };
+            unsafe {
+                copies.pipeline_barrier(&ownership_barriers(
+                    &written,
+                    transfer,
+                    (PipelineStages::ALL_TRANSFER, AccessFlags::TRANSFER_WRITE),
+                    (PipelineStages::empty(), AccessFlags::empty()),
+                ))?;
+            }
+            let release = unsafe { copies.end() }?;
+
+            let mut acquire = self.begin(dst_index)?;
+            unsafe {
+                acquire.pipeline_barrier(&ownership_barriers(
+                    &written,
+                    transfer,
+                    (PipelineStages::empty(), AccessFlags::empty()),
+                    (PipelineStages::ALL_COMMANDS, AccessFlags::MEMORY_READ),
+                ))?;
+            }
+            let acquire = unsafe { acquire.end() }?;
+
+            let semaphore = Semaphore::new(device, Default::default())?;
+            submit(&self.queue, &release, None, Some(&semaphore), None)?;
+            if let Err(err) = submit(
+                &self.graphics_queue,
+                &acquire,
+                Some(&semaphore),
+                None,
+                Some(&fence),
+            ) {
+                // The semaphore can't be destroyed while the release may still signal it
+                self.queue.with(|mut queue| queue.wait_idle())?;
+                return Err(err);
+            }
+        }
+
+        fence.wait(None)?;
+        self.head = 0;
+        Ok(())
+    }
+
+    fn begin(&self, queue_family_index: u32) -> Result<RecordingCommandBuffer, VwError> {
+        Ok(RecordingCommandBuffer::new(
+            self.command_buffer_allocator.clone(),
+            queue_family_index,
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )?)
+    }
+}
+
+/// One half of a queue family ownership transfer for each range in `written`, with the given
+/// source and destination scopes.
+fn ownership_barriers(
+    written: &[Subbuffer<[u8]>],
+    transfer: QueueFamilyOwnershipTransfer,
+    (src_stages, src_access): (PipelineStages, AccessFlags),
+    (dst_stages, dst_access): (PipelineStages, AccessFlags),
+) -> DependencyInfo {
+    DependencyInfo {
+        buffer_memory_barriers: written
+            .iter()
+            .map(|range| BufferMemoryBarrier {
+                src_stages,
+                src_access,
+                dst_stages,
+                dst_access,
+                queue_family_ownership_transfer: Some(transfer),
+                range: range.offset()..range.offset() + range.size(),
+                ..BufferMemoryBarrier::buffer(range.buffer().clone())
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// Submits a command buffer vulkano knows nothing about. Its futures only take command buffers
+/// built with automatic synchronization, which can't record ownership transfers.
+fn submit(
+    queue: &Arc<Queue>,
+    command_buffer: &CommandBuffer,
+    wait: Option<&Semaphore>,
+    signal: Option<&Semaphore>,
+    fence: Option<&Fence>,
+) -> Result<(), VwError> {
+    let command_buffers = [command_buffer.handle()];
+    let wait_semaphores = wait.map(Semaphore::handle).into_iter().collect::<Vec<_>>();
+    let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+    let signal_semaphores = signal
+        .map(Semaphore::handle)
+        .into_iter()
+        .collect::<Vec<_>>();
+    let submit_info = vk::SubmitInfo::default()
+        .wait_semaphores(&wait_semaphores)
+        .wait_dst_stage_mask(&wait_stages)
+        .command_buffers(&command_buffers)
+        .signal_semaphores(&signal_semaphores);
+
+    let fns = queue.device().fns();
+    // Holding the guard keeps vulkano from using the queue at the same time
+    queue
+        .with(|_queue| unsafe {
+            (fns.v1_0.queue_submit)(
+                queue.handle(),
+                1,
+                &submit_info,
+                fence.map_or(vk::Fence::null(), Fence::handle),
+            )
+        })
+        .result()
+        .map_err(VulkanError::from)?;
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::{
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use log::Level;
+use vulkano::instance::{
+    Instance,
+    debug::{
+        DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger,
+        DebugUtilsMessengerCallback, DebugUtilsMessengerCreateInfo,
+    },
+};
+
+use crate::vw_error::VwError;
+
+/// Forwards `VK_EXT_debug_utils` messages into the `log` facade. Validation errors are also
+/// recorded so tests can turn them into panics outside of the Vulkan callback.
+pub struct VwDebugMessenger {
+    callback: Arc<DebugUtilsMessengerCallback>,
+    _messenger: Option<DebugUtilsMessenger>,
+    debug_log: VwDebugLog,
+}
+
+impl VwDebugMessenger {
+    pub fn new(panic_on_error: bool) -> Self {
+        let debug_log = VwDebugLog::new(panic_on_error);
+
+        let callback = {
+            let debug_log = debug_log.clone();
+            // The callback only formats and logs, it never calls back into Vulkan.
+            unsafe {
+                DebugUtilsMessengerCallback::new(move |severity, message_type, data| {
+                    debug_log.report(&VwDebugMessage {
+                        level: severity_level(severity),
+                        type_name: type_name(message_type),
+                        id_name: data.message_id_name,
+                        id_number: data.message_id_number,
+                        message: data.message,
+                        objects: data
+                            .objects
+                            .map(|object| {
+                                (
+                                    format!("{:?}", object.object_type),
+                                    object.object_handle,
+                                    object.object_name,
+                                )
+                            })
+                            .collect(),
+                    });
+                })
+            }
+        };
+
+        VwDebugMessenger {
+            callback,
+            _messenger: None,
+            debug_log,
+        }
+    }
+
+    /// Create info for the instance's `pNext` chain, which covers instance creation and
+    /// destruction where a regular messenger can't exist yet.
+    pub fn create_info(&self) -> DebugUtilsMessengerCreateInfo {
+        DebugUtilsMessengerCreateInfo {
+            message_severity: DebugUtilsMessageSeverity::ERROR
+                | DebugUtilsMessageSeverity::WARNING
+                | DebugUtilsMessageSeverity::INFO
+                | DebugUtilsMessageSeverity::VERBOSE,
+            message_type: DebugUtilsMessageType::GENERAL
+                | DebugUtilsMessageType::VALIDATION
+                | DebugUtilsMessageType::PERFORMANCE,
+            ..DebugUtilsMessengerCreateInfo::user_callback(self.callback.clone())
+        }
+    }
+
+    /// Installs the messenger for the lifetime of the instance.
+    pub fn attach(&mut self, instance: Arc<Instance>) -> Result<(), VwError> {
+        self._messenger = Some(DebugUtilsMessenger::new(instance, self.create_info())?);
+        Ok(())
+    }
+
+    /// Panics if a validation error was reported and the messenger was asked to do so.
+    pub fn check(&self) {
+        self.debug_log.check();
+    }
+}
+
+/// A debug utils message with the binding's types already converted, so both backends log
+/// them the same way.
+pub struct VwDebugMessage<'a> {
+    pub level: Level,
+    pub type_name: &'static str,
+    pub id_name: Option<&'a str>,
+    pub id_number: i32,
+    pub message: &'a str,
+    /// Type, handle and name of every object the message mentions.
+    pub objects: Vec<(String, u64, Option<&'a str>)>,
+}
+
+/// The backend independent half of a debug messenger. Logs messages and remembers the first
+/// validation error for [`VwDebugLog::check`].
+#[derive(Clone)]
+pub struct VwDebugLog {
+    first_error: Arc<Mutex<Option<String>>>,
+    panic_on_error: bool,
+}
+
+impl VwDebugLog {
+    pub fn new(panic_on_error: bool) -> Self {
+        VwDebugLog {
+            first_error: Arc::new(Mutex::new(None)),
+            panic_on_error,
+        }
+    }
+
+    /// Called from the messenger callback, so it must not call back into Vulkan.
+    pub fn report(&self, message: &VwDebugMessage<'_>) {
+        let formatted = format_message(message);
+        log::log!(target: "vulkan", message.level, "{formatted}");
+
+        if message.level == Level::Error
+            && let Ok(mut first_error) = self.first_error.lock()
+        {
+            first_error.get_or_insert(formatted);
+        }
+    }
+
+    /// Panics if a validation error was reported and the log was asked to do so.
+    pub fn check(&self) {
+        if !self.panic_on_error {
+            return;
+        }
+
+        if let Ok(first_error) = self.first_error.lock()
+            && let Some(message) = first_error.as_ref()
+        {
+            panic!("Vulkan validation error: {message}");
+        }
+    }
+}
+
+fn severity_level(severity: DebugUtilsMessageSeverity) -> Level {
+    if severity.intersects(DebugUtilsMessageSeverity::ERROR) {
+        Level::Error
+    } else if severity.intersects(DebugUtilsMessageSeverity::WARNING) {
+        Level::Warn
+    } else if severity.intersects(DebugUtilsMessageSeverity::INFO) {
+        Level::Info
+    } else {
+        Level::Trace
+    }
+}
+
+fn type_name(message_type: DebugUtilsMessageType) -> &'static str {
+    if message_type.intersects(DebugUtilsMessageType::VALIDATION) {
+        "validation"
+    } else if message_type.intersects(DebugUtilsMessageType::PERFORMANCE) {
+        "performance"
+    } else {
+        "general"
+    }
+}
+
+fn format_message(message: &VwDebugMessage<'_>) -> String {
+    let mut formatted = format!(
+        "[{}] [{} ({:#x})] {}",
+        message.type_name,
+        message.id_name.unwrap_or("unnamed"),
+        message.id_number,
+        message.message,
+    );
+
+    for (object_type, object_handle, object_name) in &message.objects {
+        let _ = write!(formatted, "\n\t{object_type} {object_handle:#x}");
+        if let Some(name) = object_name {
+            let _ = write!(formatted, " \"{name}\"");
+        }
+    }
+
+    formatted
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use vulkano::{
+    format::{Format, FormatFeatures},
+    image::{
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+        view::{ImageView, ImageViewCreateInfo},
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+};
+
+use crate::{vw_engine::vw_device::VwDevice, vw_error::VwError};
+
+/// The depth attachment meshes are tested against. It has to match the color target's size, so
+/// it is recreated together with the swapchain.
+pub struct VwDepthBuffer {
+    image_view: Arc<ImageView>,
+    format: Format,
+    extent: [u32; 2],
+}
+
+impl VwDepthBuffer {
+    pub fn new(device: &VwDevice, extent: [u32; 2]) -> Result<Self, VwError> {
+        let format = choose_depth_format(device)?;
+        log::info!("Using depth format {format:?}");
+        VwDepthBuffer::with_format(device, format, extent)
+    }
+
+    /// Replaces the image with one of `extent`, keeping the format the pipeline was built for.
+    /// The old image may still be in use, command buffers in flight keep it alive.
+    pub fn recreate(&mut self, device: &VwDevice, extent: [u32; 2]) -> Result<(), VwError> {
+        if extent != self.extent {
+            *self = VwDepthBuffer::with_format(device, self.format, extent)?;
+        }
+        Ok(())
+    }
+
+    pub fn image_view(&self) -> Arc<ImageView> {
+        self.image_view.clone()
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    fn with_format(device: &VwDevice, format: Format, extent: [u32; 2]) -> Result<Self, VwError> {
+        let image_create_info = ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format,
+            extent: [extent[0], extent[1], 1],
+            usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+            ..Default::default()
+        };
+
+        let allocation_create_info = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        };
+
+        let image = Image::new(
+            device.memory_allocator().allocator(),
+            image_create_info,
+            allocation_create_info,
+        )?;
+        let image_view = ImageView::new(image.clone(), ImageViewCreateInfo::from_image(&image))?;
+
+        Ok(VwDepthBuffer {
+            image_view,
+            format,
+            extent,
+        })
+    }
+}
+
+/// `D32_SFLOAT` for its precision when the device can render to it, otherwise `D16_UNORM`, which
+/// every device supports as a depth attachment.
+fn choose_depth_format(device: &VwDevice) -> Result<Format, VwError> {
+    let properties = device
+        .logical_device()
+        .physical_device()
+        .format_properties(Format::D32_SFLOAT)?;
+
+    if properties
+        .optimal_tiling_features
+        .intersects(FormatFeatures::DEPTH_STENCIL_ATTACHMENT)
+    {
+        Ok(Format::D32_SFLOAT)
+    } else {
+        Ok(Format::D16_UNORM)
+    }
+}
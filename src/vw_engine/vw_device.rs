@@ -1,4 +1,3 @@
-use anyhow::bail;
 use std::sync::Arc;
 use vulkano::{
     Version,
@@ -12,15 +11,51 @@ use vulkano::{
     swapchain::{ColorSpace, PresentMode, Surface, SurfaceCapabilities},
 };
 
+use crate::{
+    vw_device_selection::{
+        self, VwDeviceSelection, VwDeviceType, rank_candidates, select_candidate,
+    },
+    vw_engine::vw_memory::VwMemoryAllocator,
+    vw_error::VwError,
+    vw_queue_family::{VwQueueFamilySupport, pick_graphics_present_families},
+};
+
 #[derive(Clone)]
 pub struct VwDevice {
     vk_physical_device: Arc<PhysicalDevice>,
-    vk_surface: Arc<Surface>,
+    vk_surface: Option<Arc<Surface>>,
     vk_logical_device: Arc<Device>,
     vk_graphics_queue: Arc<Queue>,
-    vk_present_queue: Arc<Queue>,
+    vk_present_queue: Option<Arc<Queue>>,
+    vk_transfer_queue: Option<Arc<Queue>>,
+    vk_compute_queue: Option<Arc<Queue>>,
+    memory_allocator: Arc<VwMemoryAllocator>,
+}
+
+#[derive(Clone)]
+pub struct VwDeviceCreateInfo {
+    /// Overridden by the `VKWIZARD_DEVICE` environment variable when it is set.
+    pub selection: VwDeviceSelection,
+    /// Creates a queue on a transfer-only family when the device has one, so uploads can run
+    /// alongside graphics work.
+    pub dedicated_transfer_queue: bool,
+    /// Creates a queue on a compute family without graphics support when the device has one.
+    pub dedicated_compute_queue: bool,
+    /// Presents from a different family than graphics whenever the device allows it, to
+    /// exercise the split-family paths on hardware where one family does both.
+    pub simulate_split_present_family: bool,
 }
 
+/// The queues created on the logical device.
+struct VwQueues {
+    graphics: Arc<Queue>,
+    present: Option<Arc<Queue>>,
+    transfer: Option<Arc<Queue>>,
+    compute: Option<Arc<Queue>>,
+}
+
+pub type VwDeviceCandidate = vw_device_selection::VwDeviceCandidate<Arc<PhysicalDevice>>;
+
 pub struct VwSwapChainSupportDetails {
     pub surface_capabilities: SurfaceCapabilities,
     pub surface_formats: Vec<(Format, ColorSpace)>,
@@ -28,38 +63,55 @@ pub struct VwSwapChainSupportDetails {
 }
 
 impl VwDevice {
-    pub fn new(vk_instance: Arc<Instance>, surface: Arc<Surface>) -> anyhow::Result<Self> {
-        let vk_physical_device = pick_physical_device(vk_instance.clone())?;
-
-        println!(
+    /// Creates the device. Without a surface no present queue is created and the swapchain
+    /// extension isn't required, which is what headless rendering uses.
+    pub fn new(
+        vk_instance: Arc<Instance>,
+        surface: Option<Arc<Surface>>,
+        create_info: &VwDeviceCreateInfo,
+    ) -> Result<Self, VwError> {
+        let selection = VwDeviceSelection::resolve(&create_info.selection);
+
+        let candidates = rank_physical_devices(vk_instance, surface.as_deref(), &selection)?;
+        let vk_physical_device = select_candidate(&candidates)?.clone();
+
+        log::info!(
             "Selected Vulkan physical device: {:?}",
             vk_physical_device.properties().device_name
         );
 
-        let (vk_logical_device, vk_graphics_queue, vk_present_queue) =
-            create_logical_device(vk_physical_device.clone(), &surface)?;
+        let (vk_logical_device, queues) =
+            create_logical_device(vk_physical_device.clone(), surface.as_deref(), create_info)?;
+        let memory_allocator = Arc::new(VwMemoryAllocator::new(vk_logical_device.clone()));
 
         Ok(VwDevice {
             vk_physical_device,
             vk_surface: surface,
             vk_logical_device,
-            vk_graphics_queue,
-            vk_present_queue,
+            vk_graphics_queue: queues.graphics,
+            vk_present_queue: queues.present,
+            vk_transfer_queue: queues.transfer,
+            vk_compute_queue: queues.compute,
+            memory_allocator,
         })
     }
 
-    pub fn swap_chain_support(&self) -> anyhow::Result<VwSwapChainSupportDetails> {
+    pub fn swap_chain_support(&self) -> Result<VwSwapChainSupportDetails, VwError> {
+        let Some(surface) = &self.vk_surface else {
+            return Err(VwError::HeadlessSwapchain);
+        };
+
         let surface_capabilities = self
             .vk_physical_device
-            .surface_capabilities(&self.vk_surface, Default::default())?;
+            .surface_capabilities(surface, Default::default())?;
 
         let surface_formats = self
             .vk_physical_device
-            .surface_formats(&self.vk_surface, Default::default())?;
+            .surface_formats(surface, Default::default())?;
 
         let present_modes = self
             .vk_physical_device
-            .surface_present_modes(&self.vk_surface, Default::default())?;
+            .surface_present_modes(surface, Default::default())?;
 
         Ok(VwSwapChainSupportDetails {
             surface_capabilities,
@@ -72,7 +124,7 @@ impl VwDevice {
         self.vk_logical_device.clone()
     }
 
-    pub fn surface(&self) -> Arc<Surface> {
+    pub fn surface(&self) -> Option<Arc<Surface>> {
         self.vk_surface.clone()
     }
 
@@ -80,179 +132,353 @@ impl VwDevice {
         self.vk_graphics_queue.clone()
     }
 
-    pub fn present_queue(&self) -> Arc<Queue> {
+    pub fn present_queue(&self) -> Option<Arc<Queue>> {
         self.vk_present_queue.clone()
     }
+
+    /// A queue on a family without graphics support, for uploads. `None` when the device has no
+    /// such family or it wasn't requested, use the graphics queue instead.
+    pub fn transfer_queue(&self) -> Option<Arc<Queue>> {
+        self.vk_transfer_queue.clone()
+    }
+
+    /// A queue on a compute family without graphics support, for async compute. `None` when the
+    /// device has no such family or it wasn't requested, use the graphics queue instead.
+    pub fn compute_queue(&self) -> Option<Arc<Queue>> {
+        self.vk_compute_queue.clone()
+    }
+
+    /// The allocator every image and buffer on this device is allocated from.
+    pub fn memory_allocator(&self) -> Arc<VwMemoryAllocator> {
+        self.memory_allocator.clone()
+    }
+
+    /// The required features plus the optional ones the device supports.
+    pub fn enabled_features(&self) -> &DeviceFeatures {
+        self.vk_logical_device.enabled_features()
+    }
 }
 
-const REQUIRED_DEVICE_EXTENSIONS: DeviceExtensions = DeviceExtensions {
+/// Extensions a device must support to be picked at all.
+pub const REQUIRED_DEVICE_EXTENSIONS: DeviceExtensions = DeviceExtensions {
     khr_swapchain: true,
-    khr_spirv_1_4: false,
-    khr_synchronization2: false,
-    khr_create_renderpass2: false,
     ..DeviceExtensions::empty()
 };
 
-const REQUIRED_FEATURES: DeviceFeatures = DeviceFeatures {
-    geometry_shader: true,
+/// Extensions enabled when the device supports them. Check the logical device's
+/// `enabled_extensions()` before relying on one.
+pub const OPTIONAL_DEVICE_EXTENSIONS: DeviceExtensions = DeviceExtensions {
+    khr_synchronization2: true,
+    ..DeviceExtensions::empty()
+};
+
+/// Features the engine itself can't run without.
+pub const REQUIRED_FEATURES: DeviceFeatures = DeviceFeatures {
     dynamic_rendering: true,
     shader_draw_parameters: true,
     extended_dynamic_state: true,
     ..DeviceFeatures::empty()
 };
 
-fn is_device_suitable(device: &Arc<PhysicalDevice>) -> bool {
-    let queue_families = device.queue_family_properties();
-    let features = device.supported_features();
-    let properties = device.properties();
-    let extensions = device.supported_extensions();
+/// Features enabled when the device supports them, so mobile and software devices aren't
+/// excluded. Check `enabled_features()` before picking a code path that uses one.
+pub const OPTIONAL_FEATURES: DeviceFeatures = DeviceFeatures {
+    geometry_shader: true,
+    sampler_anisotropy: true,
+    descriptor_indexing: true,
+    timeline_semaphore: true,
+    synchronization2: true,
+    ..DeviceFeatures::empty()
+};
 
-    if !queue_families
-        .iter()
-        .any(|qfp| qfp.queue_flags.intersects(QueueFlags::GRAPHICS))
-    {
-        return false;
-    }
-    if !features.contains(&REQUIRED_FEATURES) {
-        return false;
-    }
-    if properties.api_version < Version::V1_3 {
-        return false;
-    }
-    if !extensions.contains(&REQUIRED_DEVICE_EXTENSIONS) {
-        return false;
+/// The swapchain extension is only needed when there is a surface to present to.
+pub fn required_device_extensions(presentable: bool) -> DeviceExtensions {
+    if presentable {
+        REQUIRED_DEVICE_EXTENSIONS
+    } else {
+        DeviceExtensions {
+            khr_swapchain: false,
+            ..REQUIRED_DEVICE_EXTENSIONS
+        }
     }
+}
 
-    true
+/// Each feature set in `features` and whether the device supports it.
+pub fn features_support(
+    features: &DeviceFeatures,
+    device: &Arc<PhysicalDevice>,
+) -> Vec<(&'static str, bool)> {
+    let missing = features.difference(device.supported_features());
+    features
+        .into_iter()
+        .zip(missing)
+        .filter(|((_, wanted), _)| *wanted)
+        .map(|((name, _), (_, missing))| (name, !missing))
+        .collect()
 }
 
-fn pick_physical_device(instance: Arc<Instance>) -> anyhow::Result<Arc<PhysicalDevice>> {
-    let physical_devices = instance.enumerate_physical_devices()?;
+/// Each extension set in `extensions` and whether the device supports it.
+pub fn extensions_support(
+    extensions: &DeviceExtensions,
+    device: &Arc<PhysicalDevice>,
+) -> Vec<(&'static str, bool)> {
+    let missing = extensions.difference(device.supported_extensions());
+    extensions
+        .into_iter()
+        .zip(missing)
+        .filter(|((_, wanted), _)| *wanted)
+        .map(|((name, _), (_, missing))| (name, !missing))
+        .collect()
+}
 
-    let mut best_score = 0;
-    let mut best_device = None;
+/// Lists everything that keeps the device from being used, an empty list means it is suitable.
+fn rejection_reasons(device: &Arc<PhysicalDevice>, surface: Option<&Surface>) -> Vec<String> {
+    let properties = device.properties();
 
-    for device in physical_devices.filter(is_device_suitable) {
-        let mut score = 0;
-        let properties = device.properties();
+    let mut reasons = vec![];
 
-        if properties.device_type == PhysicalDeviceType::DiscreteGpu {
-            score += 1000;
+    if properties.api_version < Version::V1_3 {
+        reasons.push(format!(
+            "Vulkan {} is older than the required 1.3",
+            properties.api_version
+        ));
+    }
+    for (name, _) in features_support(&REQUIRED_FEATURES, device)
+        .into_iter()
+        .filter(|(_, supported)| !supported)
+    {
+        reasons.push(format!("missing feature {name}"));
+    }
+    for (name, _) in extensions_support(&required_device_extensions(surface.is_some()), device)
+        .into_iter()
+        .filter(|(_, supported)| !supported)
+    {
+        reasons.push(format!("missing extension {name}"));
+    }
+    match pick_graphics_present_queues(device.clone(), surface, false) {
+        Ok(_) => {}
+        Err(VwError::QueueFamilyNotFound(queue)) => {
+            reasons.push(format!("no {queue} queue family"));
         }
+        Err(err) => reasons.push(err.to_string()),
+    }
 
-        score += properties.max_image_dimension2_d;
+    reasons
+}
 
-        if score > best_score {
-            best_score = score;
-            best_device = Some(device);
-        }
-    }
+/// Evaluates every physical device against the requirements and `selection`, see
+/// [`rank_candidates`].
+pub fn rank_physical_devices(
+    instance: Arc<Instance>,
+    surface: Option<&Surface>,
+    selection: &VwDeviceSelection,
+) -> Result<Vec<VwDeviceCandidate>, VwError> {
+    let candidates = instance
+        .enumerate_physical_devices()?
+        .enumerate()
+        .map(|(index, physical_device)| {
+            let properties = physical_device.properties();
+
+            VwDeviceCandidate {
+                index,
+                name: properties.device_name.clone(),
+                device_type: device_type(properties.device_type),
+                vendor_id: properties.vendor_id,
+                device_id: properties.device_id,
+                max_image_dimension: properties.max_image_dimension2_d,
+                score: None,
+                rejection_reasons: rejection_reasons(&physical_device, surface),
+                physical_device,
+            }
+        })
+        .collect();
 
-    if let Some(device) = best_device {
-        Ok(device)
-    } else {
-        bail!("No Vulkan physical device found");
+    Ok(rank_candidates(candidates, selection))
+}
+
+fn device_type(device_type: PhysicalDeviceType) -> VwDeviceType {
+    match device_type {
+        PhysicalDeviceType::DiscreteGpu => VwDeviceType::DiscreteGpu,
+        PhysicalDeviceType::IntegratedGpu => VwDeviceType::IntegratedGpu,
+        PhysicalDeviceType::VirtualGpu => VwDeviceType::VirtualGpu,
+        PhysicalDeviceType::Cpu => VwDeviceType::Cpu,
+        _ => VwDeviceType::Other,
     }
 }
 
 fn pick_graphics_present_queues(
     physical_device: Arc<PhysicalDevice>,
-    surface: &Surface,
-) -> (u32, u32) {
-    let queue_families = physical_device.queue_family_properties();
-
-    // Find a queue family that supports both graphics and presentation to the given surface
-    let graphics_present_index = queue_families.iter().enumerate().find_map(|(index, qfp)| {
-        if qfp.queue_flags.intersects(QueueFlags::GRAPHICS)
-            && physical_device
-                .surface_support(index as u32, surface)
-                .unwrap()
-        {
-            Some(index as u32)
-        } else {
-            None
-        }
-    });
-
-    if let Some(index) = graphics_present_index {
-        return (index, index);
-    }
-
-    let graphics_index = queue_families
+    surface: Option<&Surface>,
+    prefer_split: bool,
+) -> Result<(u32, Option<u32>), VwError> {
+    let families = physical_device
+        .queue_family_properties()
         .iter()
         .enumerate()
-        .find_map(|(index, qfp)| {
-            if qfp.queue_flags.intersects(QueueFlags::GRAPHICS) {
-                Some(index as u32)
-            } else {
-                None
-            }
+        .map(|(index, qfp)| {
+            Ok(VwQueueFamilySupport {
+                graphics: qfp.queue_flags.intersects(QueueFlags::GRAPHICS),
+                present: match surface {
+                    Some(surface) => physical_device.surface_support(index as u32, surface)?,
+                    None => false,
+                },
+            })
         })
-        .expect("Couldn't find queue family that supports graphics");
+        .collect::<Result<Vec<_>, VwError>>()?;
 
-    let present_index = queue_families
+    pick_graphics_present_families(&families, surface.is_some(), prefer_split)
+}
+
+/// A family with all of `flags` and none of `excluded`, preferring the ones with the fewest other
+/// capabilities since those are the most likely to map to dedicated hardware.
+fn pick_dedicated_queue_family(
+    physical_device: &PhysicalDevice,
+    flags: QueueFlags,
+    excluded: QueueFlags,
+) -> Option<u32> {
+    physical_device
+        .queue_family_properties()
         .iter()
         .enumerate()
-        .find_map(|(index, _)| {
-            if physical_device
-                .surface_support(index as u32, surface)
-                .unwrap()
-            {
-                Some(index as u32)
-            } else {
-                None
-            }
-        })
-        .expect("Couldn't find queue family that supports presentation to the surface");
-
-    (graphics_index, present_index)
+        .filter(|(_, qfp)| qfp.queue_flags.contains(flags) && !qfp.queue_flags.intersects(excluded))
+        .min_by_key(|(_, qfp)| qfp.queue_flags.count())
+        .map(|(index, _)| index as u32)
 }
 
 fn create_logical_device(
     physical_device: Arc<PhysicalDevice>,
-    surface: &Surface,
-) -> anyhow::Result<(Arc<Device>, Arc<Queue>, Arc<Queue>)> {
-    // List all queue families in the device
-    let (graphics_index, present_index) =
-        pick_graphics_present_queues(physical_device.clone(), surface);
-
-    let device_queue_create_info = QueueCreateInfo {
-        queue_family_index: graphics_index,
-        queues: vec![1.0], // Queue priorities
-        ..Default::default()
+    surface: Option<&Surface>,
+    create_info: &VwDeviceCreateInfo,
+) -> Result<(Arc<Device>, VwQueues), VwError> {
+    let (graphics_index, present_index) = pick_graphics_present_queues(
+        physical_device.clone(),
+        surface,
+        create_info.simulate_split_present_family,
+    )?;
+
+    let transfer_index = create_info
+        .dedicated_transfer_queue
+        .then(|| {
+            pick_dedicated_queue_family(
+                &physical_device,
+                QueueFlags::TRANSFER,
+                QueueFlags::GRAPHICS,
+            )
+        })
+        .flatten();
+    let compute_index = create_info
+        .dedicated_compute_queue
+        .then(|| {
+            pick_dedicated_queue_family(&physical_device, QueueFlags::COMPUTE, QueueFlags::GRAPHICS)
+        })
+        .flatten();
+
+    // Each role gets its own queue as far as the family allows and shares the family's last
+    // queue after that. Graphics and present always share a queue when they share a family.
+    let queue_family_properties = physical_device.queue_family_properties();
+    let mut queue_create_infos: Vec<QueueCreateInfo> = vec![];
+    let mut add_queue = |family: u32| -> (u32, u32) {
+        let queue_count = queue_family_properties[family as usize].queue_count as usize;
+        match queue_create_infos
+            .iter_mut()
+            .find(|info| info.queue_family_index == family)
+        {
+            Some(info) if info.queues.len() < queue_count => {
+                info.queues.push(1.0);
+                (family, info.queues.len() as u32 - 1)
+            }
+            Some(info) => (family, info.queues.len() as u32 - 1),
+            None => {
+                queue_create_infos.push(QueueCreateInfo {
+                    queue_family_index: family,
+                    queues: vec![1.0], // Queue priorities
+                    ..Default::default()
+                });
+                (family, 0)
+            }
+        }
     };
 
+    let graphics_slot = add_queue(graphics_index);
+    let present_slot = match present_index {
+        Some(present_index) if present_index == graphics_index => Some(graphics_slot),
+        Some(present_index) => Some(add_queue(present_index)),
+        None => None,
+    };
+    let transfer_slot = transfer_index.map(&mut add_queue);
+    let compute_slot = compute_index.map(&mut add_queue);
+
+    // Optional features and extensions are only enabled when present, everything required was
+    // already checked when the device was picked
+    let enabled_extensions = required_device_extensions(surface.is_some())
+        .union(&OPTIONAL_DEVICE_EXTENSIONS.intersection(physical_device.supported_extensions()));
+    let enabled_features = REQUIRED_FEATURES
+        .union(&OPTIONAL_FEATURES.intersection(physical_device.supported_features()));
+
+    for (name, supported) in features_support(&OPTIONAL_FEATURES, &physical_device)
+        .into_iter()
+        .chain(extensions_support(
+            &OPTIONAL_DEVICE_EXTENSIONS,
+            &physical_device,
+        ))
+    {
+        if supported {
+            log::info!("Enabling optional {name}");
+        } else {
+            log::info!("Optional {name} is not supported");
+        }
+    }
+
     let device_create_info = DeviceCreateInfo {
-        queue_create_infos: vec![device_queue_create_info],
-        enabled_extensions: REQUIRED_DEVICE_EXTENSIONS,
-        enabled_features: REQUIRED_FEATURES,
+        queue_create_infos,
+        enabled_extensions,
+        enabled_features,
         ..Default::default()
     };
 
     let (device, queues) = Device::new(physical_device, device_create_info)?;
     let queues = queues.collect::<Vec<_>>();
 
-    let graphics_queue = queues
-        .iter()
-        .find_map(|q| {
-            if q.queue_family_index() == graphics_index {
-                Some(q.clone())
-            } else {
-                None
-            }
-        })
-        .expect("Failed to find graphics queue");
+    let find_queue = |(family, index): (u32, u32)| {
+        queues
+            .iter()
+            .find(|q| q.queue_family_index() == family && q.queue_index() == index)
+            .cloned()
+    };
 
-    let present_queue = queues
-        .iter()
-        .find_map(|q| {
-            if q.queue_family_index() == present_index {
-                Some(q.clone())
-            } else {
-                None
-            }
-        })
-        .expect("Failed to find present queue");
+    let graphics = find_queue(graphics_slot).ok_or(VwError::QueueFamilyNotFound("graphics"))?;
+    let present = match present_slot {
+        Some(present_slot) => {
+            Some(find_queue(present_slot).ok_or(VwError::QueueFamilyNotFound("presentation"))?)
+        }
+        None => None,
+    };
+    let transfer = transfer_slot.and_then(find_queue);
+    let compute = compute_slot.and_then(find_queue);
+
+    log::info!(
+        "Queue families: graphics {graphics_index}, present {present_index:?}, \
+         transfer {transfer_index:?}, compute {compute_index:?}"
+    );
+
+    Ok((
+        device,
+        VwQueues {
+            graphics,
+            present,
+            transfer,
+            compute,
+        },
+    ))
+}
 
-    Ok((device, graphics_queue, present_queue))
+impl Default for VwDeviceCreateInfo {
+    fn default() -> Self {
+        VwDeviceCreateInfo {
+            selection: VwDeviceSelection::HighPerformance,
+            dedicated_transfer_queue: true,
+            dedicated_compute_queue: true,
+            simulate_split_present_family: false,
+        }
+    }
 }
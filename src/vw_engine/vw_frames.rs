@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use vulkano::{
+    device::Device,
+    sync::{
+        self, GpuFuture,
+        future::{FenceSignalFuture, NowFuture},
+    },
+};
+
+pub type VwFrameFence = Arc<FenceSignalFuture<Box<dyn GpuFuture + Send + Sync>>>;
+
+/// Keeps track of the frames the GPU may still be working on. Each slot holds the fence of the
+/// frame that last used it; the acquire and render-finished semaphores live in the futures that
+/// vulkano chains between acquire, execute and present.
+pub struct VwFrames {
+    fences: Vec<Option<VwFrameFence>>,
+    current: usize,
+    previous: usize,
+}
+
+impl VwFrames {
+    pub fn new(frames_in_flight: usize) -> Self {
+        VwFrames {
+            fences: vec![None; frames_in_flight.max(1)],
+            current: 0,
+            previous: 0,
+        }
+    }
+
+    /// Waits until the slot of the next frame is free again and returns the future the new
+    /// frame should be chained after.
+    pub fn begin(
+        &mut self,
+        device: Arc<Device>,
+    ) -> anyhow::Result<Box<dyn GpuFuture + Send + Sync>> {
+        if let Some(fence) = &self.fences[self.current] {
+            fence.wait(None)?;
+        }
+
+        let previous_future = match self.fences[self.previous].clone() {
+            Some(fence) => fence.boxed_send_sync(),
+            None => {
+                let mut now: NowFuture = sync::now(device);
+                now.cleanup_finished();
+                now.boxed_send_sync()
+            }
+        };
+
+        Ok(previous_future)
+    }
+
+    /// Stores the fence of the frame that was just submitted and moves on to the next slot.
+    /// A frame that couldn't be submitted passes `None`.
+    pub fn end(&mut self, fence: Option<VwFrameFence>) {
+        self.fences[self.current] = fence;
+        self.previous = self.current;
+        self.current = (self.current + 1) % self.fences.len();
+    }
+
+    /// Blocks until every frame still in flight has finished on the GPU.
+    pub fn wait_all(&self) -> anyhow::Result<()> {
+        for fence in self.fences.iter().flatten() {
+            fence.wait(None)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,407 @@
+use std::{fmt::Write, sync::Arc};
+
+use serde::Serialize;
+use vulkano::{
+    VulkanLibrary,
+    device::{QueueFlags, physical::PhysicalDevice},
+    memory::MemoryHeapFlags,
+    swapchain::Surface,
+};
+
+use crate::{
+    vw_device_selection::VwDeviceSelection,
+    vw_engine::{
+        VkWizardEngineCreateInfo, create_vulkan_instance,
+        vw_device::{
+            OPTIONAL_DEVICE_EXTENSIONS, OPTIONAL_FEATURES, REQUIRED_FEATURES, extensions_support,
+            features_support, rank_physical_devices, required_device_extensions,
+        },
+    },
+    vw_window::VwWindow,
+};
+
+/// How `info` prints its report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VwInfoFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Everything the engine knows about the Vulkan installation, meant for diagnosing why a GPU
+/// isn't picked.
+#[derive(Serialize)]
+pub struct VwInfoReport {
+    pub vulkan_version: String,
+    pub instance_extensions: Vec<String>,
+    pub layers: Vec<String>,
+    /// Whether a window surface was available to check presentation support against.
+    pub surface: bool,
+    pub devices: Vec<VwDeviceReport>,
+}
+
+#[derive(Serialize)]
+pub struct VwDeviceReport {
+    pub index: usize,
+    pub name: String,
+    pub device_type: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: String,
+    pub driver_info: Option<String>,
+    pub score: Option<u32>,
+    pub rejection_reasons: Vec<String>,
+    pub queue_families: Vec<VwQueueFamilyReport>,
+    pub required_features: Vec<VwRequirementReport>,
+    pub required_extensions: Vec<VwRequirementReport>,
+    pub optional_features: Vec<VwRequirementReport>,
+    pub optional_extensions: Vec<VwRequirementReport>,
+    pub memory_heaps: Vec<VwMemoryHeapReport>,
+    /// `None` when there is no surface or the device can't present to it.
+    pub swapchain_support: Option<VwSwapchainSupportReport>,
+}
+
+#[derive(Serialize)]
+pub struct VwQueueFamilyReport {
+    pub index: u32,
+    pub queue_count: u32,
+    pub flags: Vec<&'static str>,
+    /// `None` when there is no surface to check against.
+    pub present_support: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct VwRequirementReport {
+    pub name: &'static str,
+    pub supported: bool,
+}
+
+#[derive(Serialize)]
+pub struct VwMemoryHeapReport {
+    pub size: u64,
+    pub device_local: bool,
+}
+
+#[derive(Serialize)]
+pub struct VwSwapchainSupportReport {
+    pub min_image_count: u32,
+    pub max_image_count: Option<u32>,
+    pub formats: Vec<String>,
+    pub present_modes: Vec<String>,
+}
+
+const QUEUE_FLAG_NAMES: [(QueueFlags, &str); 5] = [
+    (QueueFlags::GRAPHICS, "graphics"),
+    (QueueFlags::COMPUTE, "compute"),
+    (QueueFlags::TRANSFER, "transfer"),
+    (QueueFlags::SPARSE_BINDING, "sparse_binding"),
+    (QueueFlags::PROTECTED, "protected"),
+];
+
+impl VwInfoReport {
+    /// Collects the report. A window is opened briefly to check presentation support, unless
+    /// `create_info.headless` is set or no window can be created.
+    pub fn new(create_info: &VkWizardEngineCreateInfo) -> anyhow::Result<Self> {
+        let vk_library = VulkanLibrary::new()?;
+
+        let vw_window = match create_info.headless {
+            Some(_) => None,
+            None => match VwWindow::new(create_info.window.clone()) {
+                Ok(vw_window) => Some(vw_window),
+                Err(err) => {
+                    log::warn!("Couldn't open a window, presentation support is unknown: {err}");
+                    None
+                }
+            },
+        };
+        let surface_extensions = match &vw_window {
+            Some(vw_window) => vw_window.required_instance_extensions()?,
+            None => vec![],
+        };
+
+        let vk_instance = create_vulkan_instance(
+            vk_library.clone(),
+            &surface_extensions,
+            create_info,
+            false,
+            None,
+        )?;
+        let surface = match &vw_window {
+            Some(vw_window) => Some(vw_window.create_vk_surface(vk_instance.clone())?),
+            None => None,
+        };
+
+        let candidates = rank_physical_devices(
+            vk_instance,
+            surface.as_deref(),
+            &VwDeviceSelection::resolve(&create_info.device_selection),
+        )?;
+        let mut devices = candidates
+            .iter()
+            .map(|candidate| {
+                let physical_device = candidate.physical_device.clone();
+                let properties = physical_device.properties();
+
+                Ok(VwDeviceReport {
+                    index: candidate.index,
+                    name: candidate.name.clone(),
+                    device_type: format!("{:?}", candidate.device_type),
+                    vendor_id: candidate.vendor_id,
+                    device_id: candidate.device_id,
+                    api_version: properties.api_version.to_string(),
+                    driver_info: properties.driver_info.clone(),
+                    score: candidate.score,
+                    rejection_reasons: candidate.rejection_reasons.clone(),
+                    queue_families: queue_family_reports(&physical_device, surface.as_deref())?,
+                    required_features: requirement_reports(features_support(
+                        &REQUIRED_FEATURES,
+                        &physical_device,
+                    )),
+                    required_extensions: requirement_reports(extensions_support(
+                        &required_device_extensions(surface.is_some()),
+                        &physical_device,
+                    )),
+                    optional_features: requirement_reports(features_support(
+                        &OPTIONAL_FEATURES,
+                        &physical_device,
+                    )),
+                    optional_extensions: requirement_reports(extensions_support(
+                        &OPTIONAL_DEVICE_EXTENSIONS,
+                        &physical_device,
+                    )),
+                    memory_heaps: memory_heap_reports(&physical_device),
+                    swapchain_support: swapchain_support_report(
+                        &physical_device,
+                        surface.as_deref(),
+                    ),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        devices.sort_by_key(|device| device.index);
+
+        let layers = vk_library
+            .layer_properties()?
+            .map(|layer| layer.name().to_string())
+            .collect();
+
+        Ok(VwInfoReport {
+            vulkan_version: vk_library.api_version().to_string(),
+            instance_extensions: vk_library
+                .supported_extensions()
+                .into_iter()
+                .filter(|(_, supported)| *supported)
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            layers,
+            surface: surface.is_some(),
+            devices,
+        })
+    }
+
+    pub fn print(&self, format: VwInfoFormat) -> anyhow::Result<()> {
+        match format {
+            VwInfoFormat::Text => print!("{}", self.to_text()),
+            VwInfoFormat::Json => println!("{}", serde_json::to_string_pretty(self)?),
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        // Writing into a String can't fail
+        let _ = self.write_text(&mut text);
+        text
+    }
+
+    fn write_text(&self, text: &mut String) -> std::fmt::Result {
+        writeln!(text, "Vulkan {}", self.vulkan_version)?;
+        writeln!(text, "Layers: {}", list_or_none(&self.layers))?;
+        writeln!(text, "Instance extensions:")?;
+        for name in self.instance_extensions.iter() {
+            writeln!(text, "\t{name}")?;
+        }
+        if !self.surface {
+            writeln!(
+                text,
+                "No window surface, presentation support wasn't checked"
+            )?;
+        }
+
+        for device in self.devices.iter() {
+            writeln!(text)?;
+            writeln!(
+                text,
+                "Device #{} {} ({}, {:#06x}:{:#06x})",
+                device.index, device.name, device.device_type, device.vendor_id, device.device_id
+            )?;
+            writeln!(text, "\tVulkan {}", device.api_version)?;
+            if let Some(driver_info) = &device.driver_info {
+                writeln!(text, "\tDriver: {driver_info}")?;
+            }
+            match device.score {
+                Some(score) => writeln!(text, "\tSuitable, score {score}")?,
+                None => writeln!(text, "\tRejected: {}", device.rejection_reasons.join(", "))?,
+            }
+
+            writeln!(text, "\tQueue families:")?;
+            for family in device.queue_families.iter() {
+                let present = match family.present_support {
+                    Some(true) => ", present",
+                    Some(false) | None => "",
+                };
+                writeln!(
+                    text,
+                    "\t\t#{}: {} queues, {}{present}",
+                    family.index,
+                    family.queue_count,
+                    family.flags.join(" | ")
+                )?;
+            }
+
+            writeln!(text, "\tRequired features:")?;
+            write_requirements(text, &device.required_features)?;
+            writeln!(text, "\tRequired extensions:")?;
+            write_requirements(text, &device.required_extensions)?;
+            writeln!(text, "\tOptional features:")?;
+            write_requirements(text, &device.optional_features)?;
+            writeln!(text, "\tOptional extensions:")?;
+            write_requirements(text, &device.optional_extensions)?;
+
+            writeln!(text, "\tMemory heaps:")?;
+            for (index, heap) in device.memory_heaps.iter().enumerate() {
+                let device_local = if heap.device_local {
+                    ", device local"
+                } else {
+                    ""
+                };
+                writeln!(
+                    text,
+                    "\t\t#{index}: {} MiB{device_local}",
+                    heap.size / (1024 * 1024)
+                )?;
+            }
+
+            if let Some(swapchain_support) = &device.swapchain_support {
+                let max_image_count = swapchain_support
+                    .max_image_count
+                    .map_or("unlimited".to_string(), |count| count.to_string());
+                writeln!(
+                    text,
+                    "\tSwapchain images: {} to {max_image_count}",
+                    swapchain_support.min_image_count
+                )?;
+                writeln!(
+                    text,
+                    "\tSurface formats: {}",
+                    list_or_none(&swapchain_support.formats)
+                )?;
+                writeln!(
+                    text,
+                    "\tPresent modes: {}",
+                    list_or_none(&swapchain_support.present_modes)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn queue_family_reports(
+    physical_device: &Arc<PhysicalDevice>,
+    surface: Option<&Surface>,
+) -> anyhow::Result<Vec<VwQueueFamilyReport>> {
+    physical_device
+        .queue_family_properties()
+        .iter()
+        .enumerate()
+        .map(|(index, qfp)| {
+            let index = index as u32;
+            let present_support = match surface {
+                Some(surface) => Some(physical_device.surface_support(index, surface)?),
+                None => None,
+            };
+
+            Ok(VwQueueFamilyReport {
+                index,
+                queue_count: qfp.queue_count,
+                flags: QUEUE_FLAG_NAMES
+                    .iter()
+                    .filter(|(flag, _)| qfp.queue_flags.intersects(*flag))
+                    .map(|(_, name)| *name)
+                    .collect(),
+                present_support,
+            })
+        })
+        .collect()
+}
+
+fn requirement_reports(support: Vec<(&'static str, bool)>) -> Vec<VwRequirementReport> {
+    support
+        .into_iter()
+        .map(|(name, supported)| VwRequirementReport { name, supported })
+        .collect()
+}
+
+fn memory_heap_reports(physical_device: &Arc<PhysicalDevice>) -> Vec<VwMemoryHeapReport> {
+    physical_device
+        .memory_properties()
+        .memory_heaps
+        .iter()
+        .map(|heap| VwMemoryHeapReport {
+            size: heap.size,
+            device_local: heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL),
+        })
+        .collect()
+}
+
+/// Errors are reported as missing support, the device can't present to the surface either way.
+fn swapchain_support_report(
+    physical_device: &Arc<PhysicalDevice>,
+    surface: Option<&Surface>,
+) -> Option<VwSwapchainSupportReport> {
+    let surface = surface?;
+
+    let capabilities = physical_device
+        .surface_capabilities(surface, Default::default())
+        .ok()?;
+    let formats = physical_device
+        .surface_formats(surface, Default::default())
+        .ok()?;
+    let present_modes = physical_device
+        .surface_present_modes(surface, Default::default())
+        .ok()?;
+
+    Some(VwSwapchainSupportReport {
+        min_image_count: capabilities.min_image_count,
+        max_image_count: capabilities.max_image_count,
+        formats: formats
+            .iter()
+            .map(|(format, color_space)| format!("{format:?} {color_space:?}"))
+            .collect(),
+        present_modes: present_modes
+            .iter()
+            .map(|mode| format!("{mode:?}"))
+            .collect(),
+    })
+}
+
+fn write_requirements(text: &mut String, requirements: &[VwRequirementReport]) -> std::fmt::Result {
+    for requirement in requirements {
+        let status = if requirement.supported {
+            "supported"
+        } else {
+            "not supported"
+        };
+        writeln!(text, "\t\t{}: {status}", requirement.name)?;
+    }
+    Ok(())
+}
+
+fn list_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.join(", ")
+    }
+}
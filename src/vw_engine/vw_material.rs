@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use vulkano::{
+    descriptor_set::{
+        DescriptorSet, WriteDescriptorSet, allocator::StandardDescriptorSetAllocator,
+        layout::DescriptorSetLayout,
+    },
+    pipeline::graphics::rasterization::CullMode,
+};
+
+use crate::{
+    vw_engine::{
+        vw_buffer::{VwBuffer, VwBufferUsage},
+        vw_device::VwDevice,
+        vw_texture::VwTexture,
+    },
+    vw_error::VwError,
+    vw_scene::{VwAlphaMode, VwMaterial, VwTextureRef},
+};
+
+/// A material as the shaders see it: `MaterialUniforms` at binding 0 of set 0 and a texture at
+/// each of bindings 1 to 6. Texture slots the material leaves empty sample a white texture,
+/// which the factors then pass through unchanged.
+pub struct VwGpuMaterial {
+    descriptor_set: Arc<DescriptorSet>,
+    /// Kept for the memory statistics, the descriptor set holds the buffer itself.
+    _uniform_buffer: VwBuffer,
+    alpha_mode: VwAlphaMode,
+    double_sided: bool,
+}
+
+impl VwGpuMaterial {
+    /// `textures` are the textures added to the engine, which the material's texture references
+    /// index.
+    pub fn new(
+        device: &VwDevice,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        layout: Arc<DescriptorSetLayout>,
+        material: &VwMaterial,
+        textures: &[VwTexture],
+        white_texture: &VwTexture,
+    ) -> Result<Self, VwError> {
+        let texture_refs = texture_slots(material);
+
+        let mut writes = Vec::with_capacity(texture_refs.len() + 1);
+        for (slot, texture_ref) in texture_refs.iter().enumerate() {
+            let texture = match texture_ref {
+                Some(texture_ref) => {
+                    if texture_ref.tex_coord != 0 {
+                        log::warn!(
+                            "Material {} samples UV set {}, only set 0 is imported",
+                            material.name.as_deref().unwrap_or("without a name"),
+                            texture_ref.tex_coord
+                        );
+                    }
+                    textures.get(texture_ref.texture).ok_or_else(|| {
+                        VwError::InvalidMaterial(format!(
+                            "{} references texture {}, {} were added",
+                            material
+                                .name
+                                .as_deref()
+                                .unwrap_or("material without a name"),
+                            texture_ref.texture,
+                            textures.len()
+                        ))
+                    })?
+                }
+                None => white_texture,
+            };
+
+            writes.push(WriteDescriptorSet::image_view_sampler(
+                slot as u32 + 1,
+                texture.image_view(),
+                texture.sampler(),
+            ));
+        }
+
+        let uniforms = uniform_bytes(material, &texture_refs);
+        let uniform_buffer = VwBuffer::new(device, VwBufferUsage::Uniform, uniforms.len() as u64)?;
+        uniform_buffer.write(0, &uniforms)?;
+        writes.push(WriteDescriptorSet::buffer(0, uniform_buffer.subbuffer()));
+
+        let descriptor_set = DescriptorSet::new(descriptor_set_allocator, layout, writes, [])?;
+
+        Ok(VwGpuMaterial {
+            descriptor_set,
+            _uniform_buffer: uniform_buffer,
+            alpha_mode: material.alpha_mode,
+            double_sided: material.double_sided,
+        })
+    }
+
+    pub fn descriptor_set(&self) -> Arc<DescriptorSet> {
+        self.descriptor_set.clone()
+    }
+
+    /// Blended materials are drawn after everything else, with the blending pipeline.
+    pub fn is_blended(&self) -> bool {
+        self.alpha_mode == VwAlphaMode::Blend
+    }
+
+    /// Back faces are culled unless the material is double-sided.
+    pub fn cull_mode(&self) -> CullMode {
+        if self.double_sided {
+            CullMode::None
+        } else {
+            CullMode::Back
+        }
+    }
+}
+
+/// The material's textures in the order of the shader's bindings.
+fn texture_slots(material: &VwMaterial) -> [Option<VwTextureRef>; 6] {
+    [
+        material.base_color_texture,
+        material.metallic_roughness_texture,
+        material.normal_texture,
+        material.occlusion_texture,
+        material.emissive_texture,
+        material.specular_texture,
+    ]
+}
+
+/// Packs `MaterialUniforms` with std140 rules. The normal scale is zero without a normal
+/// texture, which makes the shader keep the interpolated normal.
+fn uniform_bytes(material: &VwMaterial, texture_refs: &[Option<VwTextureRef>; 6]) -> Vec<u8> {
+    let [r, g, b, a] = material.base_color_factor;
+    let [emissive_r, emissive_g, emissive_b] = material.emissive_factor;
+    let [specular_r, specular_g, specular_b] = material.specular_color_factor;
+    let normal_scale = if material.normal_texture.is_some() {
+        material.normal_scale
+    } else {
+        0.0
+    };
+    let alpha_mode = match material.alpha_mode {
+        VwAlphaMode::Opaque => 0,
+        VwAlphaMode::Mask => 1,
+        VwAlphaMode::Blend => 2,
+    };
+
+    let mut values = vec![
+        r,
+        g,
+        b,
+        a,
+        emissive_r,
+        emissive_g,
+        emissive_b,
+        material.metallic_factor,
+        specular_r,
+        specular_g,
+        specular_b,
+        material.roughness_factor,
+        normal_scale,
+        material.occlusion_strength,
+        material.alpha_cutoff,
+        f32::from_bits(alpha_mode),
+    ];
+    // Two rows of the UV transform per texture, each padded to a vec4
+    for texture_ref in texture_refs {
+        let transform = texture_ref.map(|texture_ref| texture_ref.transform);
+        for row in transform.unwrap_or_default().matrix() {
+            values.extend(row);
+            values.push(0.0);
+        }
+    }
+
+    values
+        .iter()
+        .flat_map(|value| value.to_ne_bytes())
+        .collect()
+}
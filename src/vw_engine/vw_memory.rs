@@ -0,0 +1,106 @@
+use std::sync::{Arc, Mutex};
+
+use vulkano::{
+    DeviceSize,
+    device::Device,
+    memory::{MemoryHeapFlags, allocator::StandardMemoryAllocator},
+};
+
+/// Allocation counters for one memory heap. Only allocations made through the engine's own
+/// resource types are counted, not the driver's or vulkano's internal ones.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VwHeapStats {
+    pub heap_index: u32,
+    /// The heap's total size as reported by the driver.
+    pub heap_size: DeviceSize,
+    pub device_local: bool,
+    pub allocation_count: u32,
+    pub allocated_bytes: DeviceSize,
+    /// The most that was allocated at once since the device was created.
+    pub peak_allocated_bytes: DeviceSize,
+}
+
+/// The memory allocator shared by everything created on a `VwDevice`. Wraps vulkano's
+/// `StandardMemoryAllocator`, which sub-allocates from large blocks and keeps host-visible
+/// blocks persistently mapped, and adds per-heap statistics on top.
+pub struct VwMemoryAllocator {
+    allocator: Arc<StandardMemoryAllocator>,
+    /// Heap index of every memory type.
+    memory_type_heaps: Vec<u32>,
+    heap_stats: Mutex<Vec<VwHeapStats>>,
+}
+
+impl VwMemoryAllocator {
+    pub fn new(device: Arc<Device>) -> Self {
+        let memory_properties = device.physical_device().memory_properties();
+        let memory_type_heaps = memory_properties
+            .memory_types
+            .iter()
+            .map(|memory_type| memory_type.heap_index)
+            .collect();
+        let heap_stats = memory_properties
+            .memory_heaps
+            .iter()
+            .enumerate()
+            .map(|(heap_index, heap)| VwHeapStats {
+                heap_index: heap_index as u32,
+                heap_size: heap.size,
+                device_local: heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL),
+                ..Default::default()
+            })
+            .collect();
+
+        VwMemoryAllocator {
+            allocator: Arc::new(StandardMemoryAllocator::new_default(device)),
+            memory_type_heaps,
+            heap_stats: Mutex::new(heap_stats),
+        }
+    }
+
+    pub fn allocator(&self) -> Arc<StandardMemoryAllocator> {
+        self.allocator.clone()
+    }
+
+    /// The heap a memory type allocates from.
+    pub fn heap_index(&self, memory_type_index: u32) -> u32 {
+        self.memory_type_heaps[memory_type_index as usize]
+    }
+
+    pub fn record_allocation(&self, heap_index: u32, size: DeviceSize) {
+        let mut heap_stats = self.heap_stats.lock().unwrap();
+        let stats = &mut heap_stats[heap_index as usize];
+        stats.allocation_count += 1;
+        stats.allocated_bytes += size;
+        stats.peak_allocated_bytes = stats.peak_allocated_bytes.max(stats.allocated_bytes);
+    }
+
+    pub fn record_free(&self, heap_index: u32, size: DeviceSize) {
+        let mut heap_stats = self.heap_stats.lock().unwrap();
+        let stats = &mut heap_stats[heap_index as usize];
+        stats.allocation_count -= 1;
+        stats.allocated_bytes -= size;
+    }
+
+    pub fn heap_stats(&self) -> Vec<VwHeapStats> {
+        self.heap_stats.lock().unwrap().clone()
+    }
+}
+
+/// Logs one line per heap. Shared by both backends, which count allocations the same way.
+pub fn log_heap_stats(heap_stats: &[VwHeapStats]) {
+    for stats in heap_stats {
+        log::info!(
+            "Heap {} ({}, {} MiB): {} allocations, {} KiB in use, {} KiB at peak",
+            stats.heap_index,
+            if stats.device_local {
+                "device local"
+            } else {
+                "host"
+            },
+            stats.heap_size / (1024 * 1024),
+            stats.allocation_count,
+            stats.allocated_bytes / 1024,
+            stats.peak_allocated_bytes / 1024
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use vulkano::{
+    DeviceSize,
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    format::Format,
+    pipeline::graphics::vertex_input::{
+        VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate,
+        VertexInputState,
+    },
+};
+
+use crate::{
+    vw_engine::{
+        vw_buffer::{VwBuffer, VwBufferUsage, VwStagingRing},
+        vw_device::VwDevice,
+    },
+    vw_error::VwError,
+};
+
+/// An attribute a vertex can carry. Each one has a fixed shader location, so shaders don't
+/// depend on which other attributes a layout has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VwVertexAttribute {
+    Position,
+    Normal,
+    Uv,
+    /// Linear RGBA.
+    Color,
+    /// XYZ plus the bitangent's handedness in W, as glTF stores it.
+    Tangent,
+}
+
+impl VwVertexAttribute {
+    /// The `[[vk::location(N)]]` the shaders read the attribute from.
+    pub fn location(self) -> u32 {
+        match self {
+            VwVertexAttribute::Position => 0,
+            VwVertexAttribute::Normal => 1,
+            VwVertexAttribute::Uv => 2,
+            VwVertexAttribute::Color => 3,
+            VwVertexAttribute::Tangent => 4,
+        }
+    }
+
+    pub fn format(self) -> Format {
+        match self {
+            VwVertexAttribute::Position | VwVertexAttribute::Normal => Format::R32G32B32_SFLOAT,
+            VwVertexAttribute::Uv => Format::R32G32_SFLOAT,
+            VwVertexAttribute::Color | VwVertexAttribute::Tangent => Format::R32G32B32A32_SFLOAT,
+        }
+    }
+
+    /// Number of `f32` components.
+    pub fn components(self) -> usize {
+        match self {
+            VwVertexAttribute::Position | VwVertexAttribute::Normal => 3,
+            VwVertexAttribute::Uv => 2,
+            VwVertexAttribute::Color | VwVertexAttribute::Tangent => 4,
+        }
+    }
+
+    pub fn size(self) -> u32 {
+        (self.components() * size_of::<f32>()) as u32
+    }
+}
+
+/// A vertex with every attribute, packed down to a [`VwVertexLayout`] before upload.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VwVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+    pub tangent: [f32; 4],
+}
+
+impl Default for VwVertex {
+    fn default() -> Self {
+        VwVertex {
+            position: [0.0; 3],
+            normal: [0.0, 0.0, 1.0],
+            uv: [0.0; 2],
+            color: [1.0; 4],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+impl VwVertex {
+    /// The colored triangle covering the whole viewport that the engines draw by default,
+    /// indexed `0, 1, 2`.
+    pub fn fullscreen_triangle() -> [VwVertex; 3] {
+        [
+            ([-1.0, -1.0, 0.0], [1.0, 0.0, 0.0, 1.0]),
+            ([3.0, -1.0, 0.0], [0.0, 1.0, 0.0, 1.0]),
+            ([-1.0, 3.0, 0.0], [0.0, 0.0, 1.0, 1.0]),
+        ]
+        .map(|(position, color)| VwVertex {
+            position,
+            color,
+            ..Default::default()
+        })
+    }
+
+    fn attribute(&self, attribute: VwVertexAttribute) -> &[f32] {
+        match attribute {
+            VwVertexAttribute::Position => &self.position,
+            VwVertexAttribute::Normal => &self.normal,
+            VwVertexAttribute::Uv => &self.uv,
+            VwVertexAttribute::Color => &self.color,
+            VwVertexAttribute::Tangent => &self.tangent,
+        }
+    }
+}
+
+/// Which attributes are interleaved in a vertex buffer, in order. Meshes and the pipeline that
+/// draws them have to agree on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VwVertexLayout {
+    attributes: Vec<VwVertexAttribute>,
+}
+
+impl Default for VwVertexLayout {
+    /// Every attribute, so imported models keep their tangents for normal mapping.
+    fn default() -> Self {
+        VwVertexLayout::new(&[
+            VwVertexAttribute::Position,
+            VwVertexAttribute::Normal,
+            VwVertexAttribute::Uv,
+            VwVertexAttribute::Color,
+            VwVertexAttribute::Tangent,
+        ])
+    }
+}
+
+impl VwVertexLayout {
+    pub fn new(attributes: &[VwVertexAttribute]) -> Self {
+        let mut unique_attributes = Vec::with_capacity(attributes.len());
+        for attribute in attributes {
+            if !unique_attributes.contains(attribute) {
+                unique_attributes.push(*attribute);
+            }
+        }
+
+        VwVertexLayout {
+            attributes: unique_attributes,
+        }
+    }
+
+    /// Size of one vertex in bytes.
+    pub fn stride(&self) -> u32 {
+        self.attributes
+            .iter()
+            .map(|attribute| attribute.size())
+            .sum()
+    }
+
+    /// Every attribute with its byte offset within a vertex.
+    pub fn offsets(&self) -> impl Iterator<Item = (VwVertexAttribute, u32)> + '_ {
+        self.attributes.iter().scan(0, |offset, &attribute| {
+            let attribute_offset = *offset;
+            *offset += attribute.size();
+            Some((attribute, attribute_offset))
+        })
+    }
+
+    /// The vertex input state for a pipeline reading this layout from binding 0.
+    pub fn vertex_input_state(&self) -> VertexInputState {
+        VertexInputState::new()
+            .binding(
+                0,
+                VertexInputBindingDescription {
+                    stride: self.stride(),
+                    input_rate: VertexInputRate::Vertex,
+                    ..Default::default()
+                },
+            )
+            .attributes(self.offsets().map(|(attribute, offset)| {
+                (
+                    attribute.location(),
+                    VertexInputAttributeDescription {
+                        binding: 0,
+                        format: attribute.format(),
+                        offset,
+                        ..Default::default()
+                    },
+                )
+            }))
+    }
+
+    /// Interleaves the layout's attributes of `vertices` into bytes ready for upload.
+    pub fn pack(&self, vertices: &[VwVertex]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(vertices.len() * self.stride() as usize);
+        for vertex in vertices {
+            for attribute in self.attributes.iter() {
+                for component in vertex.attribute(*attribute) {
+                    bytes.extend_from_slice(&component.to_ne_bytes());
+                }
+            }
+        }
+        bytes
+    }
+}
+
+/// Indexed triangle geometry in device-local vertex and index buffers.
+pub struct VwMesh {
+    vertex_buffer: VwBuffer,
+    index_buffer: VwBuffer,
+    index_count: u32,
+}
+
+impl VwMesh {
+    /// Uploads `vertices` packed to `layout` and the `u32` `indices` through `staging`. The mesh
+    /// can be drawn once the ring has been flushed.
+    pub fn new(
+        device: &VwDevice,
+        staging: &mut VwStagingRing,
+        layout: &VwVertexLayout,
+        vertices: &[VwVertex],
+        indices: &[u32],
+    ) -> Result<Self, VwError> {
+        if vertices.is_empty() || indices.is_empty() {
+            return Err(VwError::InvalidMesh("the mesh is empty".to_string()));
+        }
+        if let Some(index) = indices
+            .iter()
+            .find(|&&index| index as usize >= vertices.len())
+        {
+            return Err(VwError::InvalidMesh(format!(
+                "index {index} is out of range for {} vertices",
+                vertices.len()
+            )));
+        }
+
+        let vertex_data = layout.pack(vertices);
+        let index_data = indices
+            .iter()
+            .flat_map(|index| index.to_ne_bytes())
+            .collect::<Vec<_>>();
+
+        let vertex_buffer = VwBuffer::new(
+            device,
+            VwBufferUsage::Vertex,
+            vertex_data.len() as DeviceSize,
+        )?;
+        let index_buffer =
+            VwBuffer::new(device, VwBufferUsage::Index, index_data.len() as DeviceSize)?;
+        staging.upload(&vertex_buffer, 0, &vertex_data)?;
+        staging.upload(&index_buffer, 0, &index_data)?;
+
+        Ok(VwMesh {
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+        })
+    }
+
+    /// The triangle covering the whole viewport that the engine draws by default.
+    pub fn fullscreen_triangle(
+        device: &VwDevice,
+        staging: &mut VwStagingRing,
+        layout: &VwVertexLayout,
+    ) -> Result<Self, VwError> {
+        VwMesh::new(
+            device,
+            staging,
+            layout,
+            &VwVertex::fullscreen_triangle(),
+            &[0, 1, 2],
+        )
+    }
+
+    /// Binds the buffers and records an indexed draw. The bound pipeline must use the layout the
+    /// mesh was created with.
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), VwError> {
+        builder
+            .bind_vertex_buffers(0, self.vertex_buffer.subbuffer())?
+            .bind_index_buffer(self.index_buffer.subbuffer().reinterpret::<[u32]>())?;
+
+        unsafe {
+            builder.draw_indexed(self.index_count, 1, 0, 0, 0)?;
+        }
+
+        Ok(())
+    }
+}
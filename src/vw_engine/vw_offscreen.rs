@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use vulkano::{
+    format::Format,
+    image::{
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+        view::{ImageView, ImageViewCreateInfo},
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+};
+
+use crate::{vw_engine::vw_device::VwDevice, vw_error::VwError};
+
+/// A color image that takes the place of the swapchain when the engine runs without a window.
+pub struct VwOffscreenTarget {
+    image_view: Arc<ImageView>,
+    format: Format,
+    extent: [u32; 2],
+}
+
+impl VwOffscreenTarget {
+    pub fn new(device: &VwDevice, extent: [u32; 2]) -> Result<Self, VwError> {
+        let format = Format::B8G8R8A8_SRGB;
+
+        let image_create_info = ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format,
+            extent: [extent[0], extent[1], 1],
+            // Transfer source so the rendered frame can be read back
+            usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+            ..Default::default()
+        };
+
+        let allocation_create_info = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        };
+
+        let image = Image::new(
+            device.memory_allocator().allocator(),
+            image_create_info,
+            allocation_create_info,
+        )?;
+        let image_view = ImageView::new(image.clone(), ImageViewCreateInfo::from_image(&image))?;
+
+        Ok(VwOffscreenTarget {
+            image_view,
+            format,
+            extent,
+        })
+    }
+
+    pub fn image_view(&self) -> Arc<ImageView> {
+        self.image_view.clone()
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> [u32; 2] {
+        self.extent
+    }
+}
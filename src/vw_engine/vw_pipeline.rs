@@ -1,16 +1,76 @@
+use std::sync::Arc;
+
 use vulkano::{
+    buffer::BufferContents,
+    descriptor_set::layout::DescriptorSetLayout,
+    format::Format,
     pipeline::{
-        PipelineLayout, PipelineShaderStageCreateInfo, graphics::GraphicsPipelineCreateInfo,
+        DynamicState, GraphicsPipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+        graphics::{
+            GraphicsPipelineCreateInfo,
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            depth_stencil::{CompareOp, DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            viewport::ViewportState,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
     },
     shader::{ShaderModule, ShaderModuleCreateInfo},
 };
 
-use crate::vw_engine::vw_device::VwDevice;
+use crate::{
+    vw_engine::{vw_device::VwDevice, vw_mesh::VwVertexLayout},
+    vw_error::VwError,
+    vw_math::VwMat4,
+};
 
-pub struct VwPipeline {}
+/// `PushConstants` in shader.slang, pushed once per frame by both backends.
+#[derive(BufferContents, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct VwPushConstants {
+    /// See `VwRenderer::set_view_projection`, with the pre-rotation applied.
+    pub view_projection: VwMat4,
+    /// The world-space camera position with W = 1, or the direction towards an orthographic
+    /// camera with W = 0, see [`vw_math::eye`](crate::vw_math::eye).
+    pub eye: [f32; 4],
+    /// See [`VwColorOutput::shader_id`](crate::vw_engine::VwColorOutput::shader_id).
+    pub color_output: u32,
+    /// Non-zero when the shader applies the sRGB transfer function, see
+    /// [`VwColorOutput::needs_srgb_encoding`](crate::vw_engine::VwColorOutput::needs_srgb_encoding).
+    pub encode_srgb: u32,
+}
+
+impl VwPushConstants {
+    /// The raw bytes, for the ash backend to push.
+    pub fn as_bytes(&self) -> &[u8] {
+        // Only 4-byte fields, so the struct has no padding and every byte is initialized
+        unsafe { std::slice::from_raw_parts((self as *const Self).cast(), size_of::<Self>()) }
+    }
+}
+
+/// The pipelines meshes are drawn with, one for opaque and masked materials and one that
+/// blends. Both share a layout.
+pub struct VwPipeline {
+    pipeline: Arc<GraphicsPipeline>,
+    blend_pipeline: Arc<GraphicsPipeline>,
+    layout: Arc<PipelineLayout>,
+}
 
 impl VwPipeline {
-    pub fn new(device: &VwDevice, shader_code: &[u8]) -> anyhow::Result<Self> {
+    /// Builds the graphics pipelines for dynamic rendering into a single color attachment of
+    /// `color_format` and a depth attachment of `depth_format`, drawing meshes with
+    /// `vertex_layout`. Viewport and scissor are dynamic, so the pipelines survive resizes, and
+    /// so is the cull mode, which depends on the material.
+    pub fn new(
+        device: &VwDevice,
+        shader_code: &[u8],
+        color_format: Format,
+        depth_format: Format,
+        vertex_layout: &VwVertexLayout,
+    ) -> Result<Self, VwError> {
         let words = vulkano::shader::spirv::bytes_to_words(shader_code)?.into_owned();
         let shader_create_info = ShaderModuleCreateInfo::new(&words);
 
@@ -20,18 +80,86 @@ impl VwPipeline {
 
         let vert_entry_point = shader_module
             .entry_point("vertMain")
-            .expect("Couldn't find vertex entry point");
+            .ok_or(VwError::MissingEntryPoint("vertMain"))?;
         let frag_entry_point = shader_module
             .entry_point("fragMain")
-            .expect("Couldn't find fragment entry point");
+            .ok_or(VwError::MissingEntryPoint("fragMain"))?;
 
         let vert_stage_info = PipelineShaderStageCreateInfo::new(vert_entry_point);
         let frag_stage_info = PipelineShaderStageCreateInfo::new(frag_entry_point);
+        let stages = [vert_stage_info, frag_stage_info];
+
+        let layout = PipelineLayout::new(
+            device.logical_device(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.logical_device())?,
+        )?;
 
-        let layout = PipelineLayout::new(device.logical_device(), Default::default())?;
+        let rendering_info = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(color_format)],
+            depth_attachment_format: Some(depth_format),
+            ..Default::default()
+        };
 
-        let createInfo = GraphicsPipelineCreateInfo::layout(layout);
+        let create_info = |blend: bool| GraphicsPipelineCreateInfo {
+            stages: stages.iter().cloned().collect(),
+            vertex_input_state: Some(vertex_layout.vertex_input_state()),
+            input_assembly_state: Some(InputAssemblyState::default()),
+            viewport_state: Some(ViewportState::default()),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState::default()),
+            // Nearer fragments win, the depth buffer is cleared to the far plane every frame.
+            // Blended surfaces are tested against it but don't hide what is behind them
+            depth_stencil_state: Some(DepthStencilState {
+                depth: Some(DepthState {
+                    write_enable: !blend,
+                    compare_op: CompareOp::Less,
+                }),
+                ..Default::default()
+            }),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                1,
+                ColorBlendAttachmentState {
+                    blend: blend.then(AttachmentBlend::alpha),
+                    ..Default::default()
+                },
+            )),
+            dynamic_state: [
+                DynamicState::Viewport,
+                DynamicState::Scissor,
+                DynamicState::CullMode,
+            ]
+            .into_iter()
+            .collect(),
+            subpass: Some(rendering_info.clone().into()),
+            ..GraphicsPipelineCreateInfo::layout(layout.clone())
+        };
+
+        let pipeline = GraphicsPipeline::new(device.logical_device(), None, create_info(false))?;
+        let blend_pipeline =
+            GraphicsPipeline::new(device.logical_device(), None, create_info(true))?;
+
+        Ok(VwPipeline {
+            pipeline,
+            blend_pipeline,
+            layout,
+        })
+    }
+
+    pub fn pipeline(&self) -> Arc<GraphicsPipeline> {
+        self.pipeline.clone()
+    }
+
+    pub fn blend_pipeline(&self) -> Arc<GraphicsPipeline> {
+        self.blend_pipeline.clone()
+    }
+
+    pub fn layout(&self) -> Arc<PipelineLayout> {
+        self.layout.clone()
+    }
 
-        Ok(VwPipeline {})
+    /// The layout of the material descriptor sets, set 0.
+    pub fn material_set_layout(&self) -> Arc<DescriptorSetLayout> {
+        self.layout.set_layouts()[0].clone()
     }
 }
@@ -1,10 +1,11 @@
 use std::sync::Arc;
 
 use smallvec::SmallVec;
-use vulkano::format::Format;
+use vulkano::format::{Format, NumericFormat};
 use vulkano::image::Image;
 use vulkano::swapchain::{
-    ColorSpace, PresentMode, SurfaceCapabilities, SurfaceTransform, Swapchain, SwapchainCreateInfo,
+    ColorSpace, CompositeAlpha, PresentMode, SurfaceCapabilities, SurfaceTransform, Swapchain,
+    SwapchainCreateInfo,
 };
 use vulkano::{
     image::{
@@ -16,97 +17,568 @@ use vulkano::{
     sync::Sharing,
 };
 
-use crate::vw_engine::vw_device::VwDevice;
+use crate::{
+    vw_engine::vw_device::VwDevice,
+    vw_error::VwError,
+    vw_queue_family::{VwImageSharing, choose_image_sharing},
+};
+
+pub struct VwSwapchainCreateInfo {
+    /// Mapped to the best present mode the surface supports, can be changed later with
+    /// `set_present_policy`.
+    pub present_policy: VwPresentPolicy,
+    /// Number of images to ask for, clamped to what the surface allows. More images trade
+    /// latency for throughput.
+    pub image_count: u32,
+    /// Number of frames the CPU may record ahead of the GPU, at least one. More frames trade
+    /// latency for throughput, same as more images.
+    pub frames_in_flight: usize,
+    /// Only matters when the graphics and present queues come from different families.
+    pub sharing: VwSwapchainSharing,
+    /// Wide-gamut or HDR output to try for, falling back to SDR when the surface can't do it.
+    pub color_output: VwColorOutput,
+    /// Uses the display's current transform instead of letting the compositor rotate, see
+    /// [`VwPreRotation`].
+    pub pre_rotation: bool,
+    /// Blends the window with what is behind it, with premultiplied alpha if the surface allows.
+    pub transparent: bool,
+}
+
+/// The color space the swapchain presents in. Anything but `Sdr` needs
+/// `VK_EXT_swapchain_colorspace` on the instance and a display that reports support for it.
+/// Shaders light in linear BT.709 and encode for the output the swapchain ended up with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VwColorOutput {
+    /// sRGB. An sRGB format does the encoding in hardware, the shader does it for any other.
+    #[default]
+    Sdr,
+    /// BT.2020 primaries with the ST 2084 (PQ) transfer function. The shader converts the gamut
+    /// and encodes PQ, with SDR white at 203 nits.
+    Hdr10,
+    /// Linear extended sRGB in a half-float format, values above 1.0 are brighter than SDR white.
+    /// The shader output is presented as is.
+    ScRgb,
+    /// Display-P3 primaries with the sRGB transfer function. The shader converts the gamut, the
+    /// transfer function is applied as for `Sdr`.
+    DisplayP3,
+}
+
+impl VwColorOutput {
+    /// Surface formats in order of preference.
+    pub fn preferred_surface_formats(self) -> &'static [(Format, ColorSpace)] {
+        match self {
+            VwColorOutput::Sdr => &[
+                (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear),
+                (Format::R8G8B8A8_SRGB, ColorSpace::SrgbNonLinear),
+            ],
+            VwColorOutput::Hdr10 => &[
+                (Format::A2B10G10R10_UNORM_PACK32, ColorSpace::Hdr10St2084),
+                (Format::A2R10G10B10_UNORM_PACK32, ColorSpace::Hdr10St2084),
+            ],
+            VwColorOutput::ScRgb => {
+                &[(Format::R16G16B16A16_SFLOAT, ColorSpace::ExtendedSrgbLinear)]
+            }
+            VwColorOutput::DisplayP3 => &[
+                (
+                    Format::A2B10G10R10_UNORM_PACK32,
+                    ColorSpace::DisplayP3NonLinear,
+                ),
+                (Format::B8G8R8A8_SRGB, ColorSpace::DisplayP3NonLinear),
+                (Format::R8G8B8A8_SRGB, ColorSpace::DisplayP3NonLinear),
+            ],
+        }
+    }
+
+    /// `colorOutput` in shader.slang.
+    pub fn shader_id(self) -> u32 {
+        match self {
+            VwColorOutput::Sdr => 0,
+            VwColorOutput::Hdr10 => 1,
+            VwColorOutput::ScRgb => 2,
+            VwColorOutput::DisplayP3 => 3,
+        }
+    }
+
+    /// Whether the shader has to apply the sRGB transfer function itself, because `format`
+    /// stores what it is given. Only `Sdr` and `DisplayP3` use that curve.
+    pub fn needs_srgb_encoding(self, format: Format) -> bool {
+        matches!(self, VwColorOutput::Sdr | VwColorOutput::DisplayP3)
+            && format.numeric_format_color() != Some(NumericFormat::SRGB)
+    }
+
+    /// Whether the output needs `VK_EXT_swapchain_colorspace`.
+    pub fn needs_colorspace_extension(self) -> bool {
+        self != VwColorOutput::Sdr
+    }
+}
+
+/// What the application wants from presentation, mapped against the surface's present modes.
+/// Fifo is always supported, so every policy falls back to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VwPresentPolicy {
+    /// Waits for vertical blank, no tearing, the frame rate is capped at the refresh rate.
+    VSync,
+    /// Renders as fast as possible and presents the newest frame at vertical blank, no tearing.
+    #[default]
+    LowLatency,
+    /// Presents immediately and may tear, meant for benchmarks.
+    Uncapped,
+    /// Like `VSync`, but presents late frames immediately instead of waiting another blank.
+    Adaptive,
+}
+
+impl VwPresentPolicy {
+    /// Present modes in order of preference.
+    pub fn preferred_present_modes(self) -> &'static [PresentMode] {
+        match self {
+            VwPresentPolicy::VSync => &[PresentMode::Fifo],
+            VwPresentPolicy::LowLatency => &[
+                PresentMode::Mailbox,
+                PresentMode::Immediate,
+                PresentMode::Fifo,
+            ],
+            VwPresentPolicy::Uncapped => &[
+                PresentMode::Immediate,
+                PresentMode::Mailbox,
+                PresentMode::Fifo,
+            ],
+            VwPresentPolicy::Adaptive => &[PresentMode::FifoRelaxed, PresentMode::Fifo],
+        }
+    }
+
+    /// The next policy, for cycling through them with a key.
+    pub fn next(self) -> Self {
+        match self {
+            VwPresentPolicy::VSync => VwPresentPolicy::LowLatency,
+            VwPresentPolicy::LowLatency => VwPresentPolicy::Uncapped,
+            VwPresentPolicy::Uncapped => VwPresentPolicy::Adaptive,
+            VwPresentPolicy::Adaptive => VwPresentPolicy::VSync,
+        }
+    }
+}
+
+/// The rotation the renderer has to apply itself because the swapchain was created with the
+/// display's transform, e.g. on a phone held in landscape. The renderer compensates by rotating
+/// clip space by the same angle after the view-projection matrix.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VwPreRotation {
+    #[default]
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl VwPreRotation {
+    /// `None` for mirrored transforms, which the engine doesn't pre-transform for.
+    pub fn from_transform(transform: SurfaceTransform) -> Option<Self> {
+        match transform {
+            SurfaceTransform::Identity => Some(VwPreRotation::Identity),
+            SurfaceTransform::Rotate90 => Some(VwPreRotation::Rotate90),
+            SurfaceTransform::Rotate180 => Some(VwPreRotation::Rotate180),
+            SurfaceTransform::Rotate270 => Some(VwPreRotation::Rotate270),
+            _ => None,
+        }
+    }
+
+    /// Whether the images are in the display's other orientation, so their width and height are
+    /// swapped relative to the window.
+    pub fn swaps_extent(self) -> bool {
+        matches!(self, VwPreRotation::Rotate90 | VwPreRotation::Rotate270)
+    }
+
+    /// Clockwise rotation in degrees.
+    pub fn degrees(self) -> u32 {
+        match self {
+            VwPreRotation::Identity => 0,
+            VwPreRotation::Rotate90 => 90,
+            VwPreRotation::Rotate180 => 180,
+            VwPreRotation::Rotate270 => 270,
+        }
+    }
+}
+
+/// How swapchain images are shared when graphics and presentation use different queue families.
+/// With a single family the images are always exclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VwSwapchainSharing {
+    /// Images are usable from both families without ownership transfers.
+    #[default]
+    Concurrent,
+    /// Images belong to one family at a time and are handed to the present family with
+    /// ownership-transfer barriers, which can be faster on some hardware. Only the ash backend
+    /// records those, the vulkano backend warns and falls back to `Concurrent`.
+    Exclusive,
+}
 
 pub struct VwSwapchain {
     swapchain: Arc<Swapchain>,
+    present_policy: VwPresentPolicy,
+    requested_image_count: u32,
+    frames_in_flight: usize,
     images: Vec<Arc<Image>>,
     surface_format: Format,
+    color_output: VwColorOutput,
+    pre_rotation: bool,
     extent: [u32; 2],
     image_views: Vec<Arc<ImageView>>,
 }
 
 impl VwSwapchain {
-    pub fn new(device: &VwDevice) -> anyhow::Result<Self> {
+    /// Creates the swapchain. `drawable_extent` is the window's size in pixels and is used when
+    /// the surface leaves the extent up to the application.
+    pub fn new(
+        device: &VwDevice,
+        drawable_extent: [u32; 2],
+        create_info: &VwSwapchainCreateInfo,
+    ) -> Result<Self, VwError> {
         let swapchain_support = device.swap_chain_support()?;
-        let (surface_format, color_space) =
-            choose_surface_format(&swapchain_support.surface_formats);
-        let present_mode = choose_present_mode(&swapchain_support.present_modes);
-        let extent = choose_extent(&swapchain_support.surface_capabilities, 1920, 1080);
-
-        let image_count = 3.clamp(
-            swapchain_support.surface_capabilities.min_image_count,
-            swapchain_support
-                .surface_capabilities
-                .max_image_count
-                .unwrap_or(u32::MAX),
+        let (Some(surface), Some(present_queue)) = (device.surface(), device.present_queue())
+        else {
+            return Err(VwError::HeadlessSwapchain);
+        };
+
+        // Without the extension the driver must not be handed any other color space
+        let mut color_output = create_info.color_output;
+        if color_output.needs_colorspace_extension()
+            && !device
+                .logical_device()
+                .instance()
+                .enabled_extensions()
+                .ext_swapchain_colorspace
+        {
+            log::warn!(
+                "VK_EXT_swapchain_colorspace is not enabled, using {:?} output",
+                VwColorOutput::Sdr
+            );
+            color_output = VwColorOutput::Sdr;
+        }
+
+        let (color_output, surface_format, color_space) =
+            choose_surface_format(&swapchain_support.surface_formats, color_output);
+        let present_mode =
+            choose_present_mode(&swapchain_support.present_modes, create_info.present_policy);
+        let image_count = choose_image_count(
+            &swapchain_support.surface_capabilities,
+            create_info.image_count,
+        );
+        let pre_transform = choose_pre_transform(
+            &swapchain_support.surface_capabilities,
+            create_info.pre_rotation,
+        );
+        let extent = choose_extent(
+            &swapchain_support.surface_capabilities,
+            drawable_extent,
+            pre_transform,
+        );
+        let composite_alpha = choose_composite_alpha(
+            &swapchain_support.surface_capabilities,
+            create_info.transparent,
         );
 
-        let mut create_info: SwapchainCreateInfo = SwapchainCreateInfo {
+        let mut swapchain_create_info: SwapchainCreateInfo = SwapchainCreateInfo {
             flags: SwapchainCreateFlags::empty(),
-            min_image_count: swapchain_support.surface_capabilities.min_image_count,
+            min_image_count: image_count,
             image_format: surface_format,
             image_color_space: color_space,
             image_extent: extent,
             image_array_layers: 1,
             image_usage: vulkano::image::ImageUsage::COLOR_ATTACHMENT,
             image_sharing: Sharing::Exclusive,
-            pre_transform: SurfaceTransform::Identity, // No transformation
-            // pre_transform: swapchain_support.surface_capabilities.current_transform, // No transformation
-            composite_alpha: vulkano::swapchain::CompositeAlpha::Opaque, // Window is opaque
+            pre_transform,
+            composite_alpha,
             present_mode,
             clipped: true, // We don't care about pixels that are obscured by other window or outside the screen
             ..Default::default()
         };
 
-        // If the queues are different, we need to set the sharing mode to concurrent
-        if device.graphics_queue().queue_index() != device.present_queue().queue_index() {
-            create_info.image_sharing = Sharing::Concurrent(SmallVec::from_slice(&[
-                device.graphics_queue().queue_index(),
-                device.present_queue().queue_index(),
-            ]));
+        match choose_image_sharing(
+            device.graphics_queue().queue_family_index(),
+            present_queue.queue_family_index(),
+            create_info.sharing,
+        ) {
+            VwImageSharing::Exclusive => {}
+            VwImageSharing::Concurrent(queue_families) => {
+                log::info!(
+                    "Graphics family {} and present family {} differ, sharing swapchain images \
+                     concurrently",
+                    queue_families[0],
+                    queue_families[1]
+                );
+                swapchain_create_info.image_sharing =
+                    Sharing::Concurrent(SmallVec::from_slice(&queue_families));
+            }
+            // Auto command buffers can't record the release and acquire barriers
+            VwImageSharing::ExclusiveTransfer {
+                graphics_family,
+                present_family,
+            } => {
+                log::warn!(
+                    "Exclusive swapchain sharing needs ownership transfers this backend can't \
+                     record, sharing images concurrently between families {} and {} instead",
+                    graphics_family,
+                    present_family
+                );
+                swapchain_create_info.image_sharing =
+                    Sharing::Concurrent(SmallVec::from_slice(&[graphics_family, present_family]));
+            }
         }
 
         let (swapchain, images) = vulkano::swapchain::Swapchain::new(
             device.logical_device(),
-            device.surface(),
-            create_info,
-        )?;
+            surface,
+            swapchain_create_info,
+        )
+        .map_err(VwError::SwapchainCreation)?;
 
-        let image_views = create_image_views(surface_format, images.clone());
+        let image_views = create_image_views(surface_format, images.clone())?;
 
         Ok(VwSwapchain {
             swapchain,
+            present_policy: create_info.present_policy,
+            requested_image_count: create_info.image_count,
+            frames_in_flight: create_info.frames_in_flight.max(1),
             images,
             surface_format,
+            color_output,
+            pre_rotation: create_info.pre_rotation,
             image_views,
             extent,
         })
     }
-}
 
-fn choose_surface_format(available_formats: &[(Format, ColorSpace)]) -> (Format, ColorSpace) {
-    for (format, color_space) in available_formats.iter() {
-        if *format == Format::B8G8R8A8_SRGB && *color_space == ColorSpace::SrgbNonLinear {
-            return (*format, *color_space);
+    /// Rebuilds the swapchain for the current surface size, passing the existing swapchain as
+    /// `old_swapchain` so images still being presented are handed over. Returns `false` without
+    /// recreating anything while the surface has a zero-sized extent, e.g. when minimized.
+    pub fn recreate(
+        &mut self,
+        device: &VwDevice,
+        drawable_extent: [u32; 2],
+    ) -> Result<bool, VwError> {
+        let swapchain_support = device.swap_chain_support()?;
+        // The display may have been rotated since the swapchain was created
+        let pre_transform =
+            choose_pre_transform(&swapchain_support.surface_capabilities, self.pre_rotation);
+        let extent = choose_extent(
+            &swapchain_support.surface_capabilities,
+            drawable_extent,
+            pre_transform,
+        );
+
+        if extent.contains(&0) {
+            return Ok(false);
         }
+
+        let present_mode =
+            choose_present_mode(&swapchain_support.present_modes, self.present_policy);
+
+        let (swapchain, images) = self
+            .swapchain
+            .recreate(SwapchainCreateInfo {
+                min_image_count: choose_image_count(
+                    &swapchain_support.surface_capabilities,
+                    self.requested_image_count,
+                ),
+                image_extent: extent,
+                pre_transform,
+                present_mode,
+                ..self.swapchain.create_info()
+            })
+            .map_err(VwError::SwapchainCreation)?;
+
+        self.image_views = create_image_views(self.surface_format, images.clone())?;
+        self.swapchain = swapchain;
+        self.images = images;
+        self.extent = extent;
+
+        Ok(true)
+    }
+
+    /// Takes effect the next time the swapchain is recreated.
+    pub fn set_present_policy(&mut self, present_policy: VwPresentPolicy) {
+        self.present_policy = present_policy;
+    }
+
+    pub fn present_policy(&self) -> VwPresentPolicy {
+        self.present_policy
+    }
+
+    /// The image count the swapchain was created with, after clamping to the surface limits.
+    pub fn min_image_count(&self) -> u32 {
+        self.swapchain.create_info().min_image_count
+    }
+
+    /// The number of images the implementation actually created, which can be more than asked
+    /// for.
+    pub fn image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    /// How many frames the engine keeps in flight while presenting to this swapchain.
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight
+    }
+
+    /// The rotation projection matrices have to apply, `Identity` unless pre-rotation was asked
+    /// for and the display is rotated.
+    pub fn pre_rotation(&self) -> VwPreRotation {
+        VwPreRotation::from_transform(self.swapchain.create_info().pre_transform)
+            .unwrap_or_default()
+    }
+
+    /// Whether the window is blended with what is behind it, so the clear color needs a zero
+    /// alpha.
+    pub fn is_transparent(&self) -> bool {
+        matches!(
+            self.swapchain.create_info().composite_alpha,
+            CompositeAlpha::PreMultiplied | CompositeAlpha::PostMultiplied
+        )
+    }
+
+    pub fn swapchain(&self) -> Arc<Swapchain> {
+        self.swapchain.clone()
+    }
+
+    pub fn image_views(&self) -> &[Arc<ImageView>] {
+        &self.image_views
+    }
+
+    pub fn surface_format(&self) -> Format {
+        self.surface_format
+    }
+
+    /// The output that was actually chosen, which is `Sdr` when the requested one wasn't
+    /// available. Shaders use it to pick their tonemapping and encoding.
+    pub fn color_output(&self) -> VwColorOutput {
+        self.color_output
+    }
+
+    pub fn extent(&self) -> [u32; 2] {
+        self.extent
     }
-    available_formats[0]
 }
 
-fn choose_present_mode(present_modes: &[PresentMode]) -> PresentMode {
-    for present_mode in present_modes.iter() {
-        if *present_mode == PresentMode::Mailbox {
-            return *present_mode;
-        }
+/// Picks the first supported format for `requested`, then for SDR, then whatever the surface
+/// lists first.
+fn choose_surface_format(
+    available_formats: &[(Format, ColorSpace)],
+    requested: VwColorOutput,
+) -> (VwColorOutput, Format, ColorSpace) {
+    let find = |output: VwColorOutput| {
+        output
+            .preferred_surface_formats()
+            .iter()
+            .find(|surface_format| available_formats.contains(surface_format))
+            .map(|&(format, color_space)| (output, format, color_space))
+    };
+
+    if let Some(chosen) = find(requested) {
+        log::info!("{requested:?} output uses {:?} in {:?}", chosen.1, chosen.2);
+        return chosen;
+    }
+    if requested != VwColorOutput::Sdr {
+        log::warn!("The surface doesn't support {requested:?} output, falling back to SDR");
+    }
+
+    find(VwColorOutput::Sdr).unwrap_or_else(|| {
+        let (format, color_space) = available_formats[0];
+        (VwColorOutput::Sdr, format, color_space)
+    })
+}
+
+fn choose_present_mode(present_modes: &[PresentMode], policy: VwPresentPolicy) -> PresentMode {
+    let present_mode = policy
+        .preferred_present_modes()
+        .iter()
+        .copied()
+        .find(|present_mode| present_modes.contains(present_mode))
+        .unwrap_or(PresentMode::Fifo);
+
+    log::info!("Present policy {policy:?} uses {present_mode:?}");
+    present_mode
+}
+
+fn choose_image_count(capabilities: &SurfaceCapabilities, requested: u32) -> u32 {
+    requested.clamp(
+        capabilities.min_image_count,
+        capabilities.max_image_count.unwrap_or(u32::MAX),
+    )
+}
+
+/// Identity lets the compositor rotate, which costs it a pass on mobile. With `pre_rotation` the
+/// current transform is used when it is a plain rotation the renderer can compensate for.
+fn choose_pre_transform(
+    capabilities: &SurfaceCapabilities,
+    pre_rotation: bool,
+) -> SurfaceTransform {
+    let current_transform = capabilities.current_transform;
+    let supported = capabilities.supported_transforms;
+
+    if pre_rotation && VwPreRotation::from_transform(current_transform).is_some() {
+        current_transform
+    } else if supported.contains_enum(SurfaceTransform::Identity) {
+        SurfaceTransform::Identity
+    } else {
+        log::warn!(
+            "The surface doesn't support the identity transform, using {current_transform:?}"
+        );
+        current_transform
     }
-    PresentMode::Fifo
 }
 
-fn choose_extent(capabilities: &SurfaceCapabilities, width: u32, height: u32) -> [u32; 2] {
+/// Opaque when supported, or the premultiplied and postmultiplied modes for transparent windows.
+/// Otherwise the first mode the surface supports, since at least one always is.
+fn choose_composite_alpha(capabilities: &SurfaceCapabilities, transparent: bool) -> CompositeAlpha {
+    let supported = capabilities.supported_composite_alpha;
+    let preferred: &[CompositeAlpha] = if transparent {
+        &[
+            CompositeAlpha::PreMultiplied,
+            CompositeAlpha::PostMultiplied,
+            CompositeAlpha::Inherit,
+            CompositeAlpha::Opaque,
+        ]
+    } else {
+        &[
+            CompositeAlpha::Opaque,
+            CompositeAlpha::Inherit,
+            CompositeAlpha::PreMultiplied,
+            CompositeAlpha::PostMultiplied,
+        ]
+    };
+
+    let composite_alpha = preferred
+        .iter()
+        .copied()
+        .find(|&composite_alpha| supported.contains_enum(composite_alpha))
+        .unwrap_or(CompositeAlpha::Opaque);
+
+    if transparent
+        && !matches!(
+            composite_alpha,
+            CompositeAlpha::PreMultiplied | CompositeAlpha::PostMultiplied
+        )
+    {
+        log::warn!("The surface can't blend with the desktop, using {composite_alpha:?} alpha");
+    }
+    composite_alpha
+}
+
+/// The surface's extent, or the drawable size when the surface leaves it to the swapchain. The
+/// drawable size is in the window's orientation, so it is swapped when `pre_transform` rotates
+/// the images by a quarter turn.
+fn choose_extent(
+    capabilities: &SurfaceCapabilities,
+    drawable_extent: [u32; 2],
+    pre_transform: SurfaceTransform,
+) -> [u32; 2] {
     if let Some([width, height]) = capabilities.current_extent {
         [width, height]
     } else {
+        let [mut width, mut height] = drawable_extent;
+        if VwPreRotation::from_transform(pre_transform).is_some_and(VwPreRotation::swaps_extent) {
+            (width, height) = (height, width);
+        }
+
         let [min_width, min_height] = capabilities.min_image_extent;
         let [max_width, max_height] = capabilities.max_image_extent;
 
@@ -119,7 +591,10 @@ fn choose_extent(capabilities: &SurfaceCapabilities, width: u32, height: u32) ->
     }
 }
 
-fn create_image_views(surface_format: Format, images: Vec<Arc<Image>>) -> Vec<Arc<ImageView>> {
+fn create_image_views(
+    surface_format: Format,
+    images: Vec<Arc<Image>>,
+) -> Result<Vec<Arc<ImageView>>, VwError> {
     images
         .iter()
         .map(|image| {
@@ -139,7 +614,7 @@ fn create_image_views(surface_format: Format, images: Vec<Arc<Image>>) -> Vec<Ar
                 },
                 ..Default::default()
             };
-            ImageView::new(image.clone(), create_info).expect("Failed to create image view")
+            Ok(ImageView::new(image.clone(), create_info)?)
         })
-        .collect::<Vec<_>>()
+        .collect()
 }
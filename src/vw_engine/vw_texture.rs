@@ -0,0 +1,343 @@
+use std::{path::Path, sync::Arc};
+
+use image::DynamicImage;
+use vulkano::{
+    DeviceSize,
+    command_buffer::{
+        AutoCommandBufferBuilder, BlitImageInfo, CommandBufferUsage, CopyBufferToImageInfo,
+        ImageBlit, allocator::StandardCommandBufferAllocator,
+    },
+    format::{Format, FormatFeatures},
+    image::{
+        Image, ImageCreateInfo, ImageLayout, ImageMemory, ImageSubresourceLayers, ImageType,
+        ImageUsage,
+        sampler::{
+            Filter, LOD_CLAMP_NONE, Sampler, SamplerAddressMode, SamplerCreateInfo,
+            SamplerMipmapMode,
+        },
+        view::ImageView,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    sync::GpuFuture,
+};
+
+use crate::{
+    vw_engine::{
+        vw_buffer::{VwBuffer, VwBufferUsage},
+        vw_device::VwDevice,
+        vw_memory::VwMemoryAllocator,
+    },
+    vw_error::VwError,
+};
+
+/// Decoded pixels of a 2D texture, in a format the GPU samples directly.
+#[derive(Clone, Debug)]
+pub struct VwTextureData {
+    pub width: u32,
+    pub height: u32,
+    pub format: Format,
+    pub pixels: Vec<u8>,
+}
+
+impl VwTextureData {
+    /// Decodes a PNG, JPEG, TGA or Radiance HDR file. HDR images become 32-bit float RGBA,
+    /// everything else 8-bit RGBA, sRGB encoded when `srgb` is set. Colors should be sRGB, data
+    /// such as normal or roughness maps shouldn't.
+    pub fn load(path: impl AsRef<Path>, srgb: bool) -> Result<Self, VwError> {
+        let image = image::open(path.as_ref())?;
+
+        Ok(match image {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+                let image = image.into_rgba32f();
+                VwTextureData {
+                    width: image.width(),
+                    height: image.height(),
+                    format: Format::R32G32B32A32_SFLOAT,
+                    pixels: image
+                        .into_raw()
+                        .into_iter()
+                        .flat_map(f32::to_ne_bytes)
+                        .collect(),
+                }
+            }
+            _ => {
+                let image = image.into_rgba8();
+                VwTextureData {
+                    width: image.width(),
+                    height: image.height(),
+                    format: rgba8_format(srgb),
+                    pixels: image.into_raw(),
+                }
+            }
+        })
+    }
+
+    /// Wraps 8-bit RGBA pixels, linear until [`set_srgb`](Self::set_srgb) says otherwise.
+    pub fn rgba8(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        VwTextureData {
+            width,
+            height,
+            format: rgba8_format(false),
+            pixels,
+        }
+    }
+
+    /// Switches 8-bit RGBA between sRGB and linear decoding. Float pixels are always linear and
+    /// keep their format.
+    pub fn set_srgb(&mut self, srgb: bool) {
+        if matches!(self.format, Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB) {
+            self.format = rgba8_format(srgb);
+        }
+    }
+}
+
+fn rgba8_format(srgb: bool) -> Format {
+    if srgb {
+        Format::R8G8B8A8_SRGB
+    } else {
+        Format::R8G8B8A8_UNORM
+    }
+}
+
+/// How a texture is sampled. The default filters linearly everywhere, repeats, and uses as much
+/// anisotropy as the device allows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VwSamplerCreateInfo {
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    pub mipmap_mode: SamplerMipmapMode,
+    /// U, V and W.
+    pub address_mode: [SamplerAddressMode; 3],
+    /// Only applied when the device has the `sampler_anisotropy` feature, and clamped to its
+    /// limit.
+    pub max_anisotropy: Option<f32>,
+}
+
+impl Default for VwSamplerCreateInfo {
+    fn default() -> Self {
+        VwSamplerCreateInfo {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_mode: SamplerMipmapMode::Linear,
+            address_mode: [SamplerAddressMode::Repeat; 3],
+            max_anisotropy: Some(16.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VwTextureCreateInfo {
+    /// Generate a full mip chain. Skipped for formats that can't be blitted with linear
+    /// filtering, the texture then has a single level.
+    pub generate_mipmaps: bool,
+    pub sampler: VwSamplerCreateInfo,
+}
+
+impl Default for VwTextureCreateInfo {
+    fn default() -> Self {
+        VwTextureCreateInfo {
+            generate_mipmaps: true,
+            sampler: VwSamplerCreateInfo::default(),
+        }
+    }
+}
+
+/// A sampled 2D image with its view and sampler.
+pub struct VwTexture {
+    image: Arc<Image>,
+    image_view: Arc<ImageView>,
+    sampler: Arc<Sampler>,
+    heap_index: u32,
+    size: DeviceSize,
+    memory_allocator: Arc<VwMemoryAllocator>,
+}
+
+impl VwTexture {
+    /// Uploads `data` through a staging buffer, generates the mip chain and creates the sampler.
+    /// Blits need a graphics queue, so the upload is submitted there rather than through the
+    /// transfer queue `VwStagingRing` uses, and waited on.
+    pub fn new(
+        device: &VwDevice,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        data: &VwTextureData,
+        create_info: &VwTextureCreateInfo,
+    ) -> Result<Self, VwError> {
+        let expected_size =
+            data.width as DeviceSize * data.height as DeviceSize * data.format.block_size();
+        if expected_size == 0 || data.pixels.len() as DeviceSize != expected_size {
+            return Err(VwError::InvalidTexture(format!(
+                "{} bytes of pixels for a {}x{} {:?} texture",
+                data.pixels.len(),
+                data.width,
+                data.height,
+                data.format
+            )));
+        }
+
+        let memory_allocator = device.memory_allocator();
+        let format_features = device
+            .logical_device()
+            .physical_device()
+            .format_properties(data.format)?
+            .optimal_tiling_features;
+
+        let blittable = format_features.contains(
+            FormatFeatures::BLIT_SRC
+                | FormatFeatures::BLIT_DST
+                | FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR,
+        );
+        let mip_levels = if create_info.generate_mipmaps && blittable {
+            u32::BITS - data.width.max(data.height).leading_zeros()
+        } else {
+            if create_info.generate_mipmaps {
+                log::warn!(
+                    "{:?} can't be blitted with linear filtering, skipping mipmaps",
+                    data.format
+                );
+            }
+            1
+        };
+
+        let mut usage = ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST;
+        if mip_levels > 1 {
+            usage |= ImageUsage::TRANSFER_SRC;
+        }
+        let image = Image::new(
+            memory_allocator.allocator(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: data.format,
+                extent: [data.width, data.height, 1],
+                mip_levels,
+                usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )?;
+
+        let staging = VwBuffer::from_data(device, VwBufferUsage::Staging, &data.pixels)?;
+        let queue = device.graphics_queue();
+        let mut builder = AutoCommandBufferBuilder::primary(
+            command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        // The command buffer inserts the layout transitions and barriers between the copy and
+        // each blit, which reads the level the previous one wrote
+        builder.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+            staging.subbuffer(),
+            image.clone(),
+        ))?;
+        for level in 1..mip_levels {
+            let src_extent = mip_extent(data, level - 1);
+            let dst_extent = mip_extent(data, level);
+
+            builder.blit_image(BlitImageInfo {
+                src_image_layout: ImageLayout::TransferSrcOptimal,
+                dst_image_layout: ImageLayout::TransferDstOptimal,
+                regions: [ImageBlit {
+                    src_subresource: ImageSubresourceLayers {
+                        mip_level: level - 1,
+                        ..image.subresource_layers()
+                    },
+                    src_offsets: [[0; 3], [src_extent[0], src_extent[1], 1]],
+                    dst_subresource: ImageSubresourceLayers {
+                        mip_level: level,
+                        ..image.subresource_layers()
+                    },
+                    dst_offsets: [[0; 3], [dst_extent[0], dst_extent[1], 1]],
+                    ..Default::default()
+                }]
+                .into(),
+                filter: Filter::Linear,
+                ..BlitImageInfo::images(image.clone(), image.clone())
+            })?;
+        }
+
+        let command_buffer = builder.build()?;
+        vulkano::sync::now(device.logical_device())
+            .then_execute(queue, command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        let image_view = ImageView::new_default(image.clone())?;
+        let sampler = create_sampler(device, &create_info.sampler)?;
+
+        let (heap_index, size) = match image.memory() {
+            ImageMemory::Normal(allocations) => (
+                memory_allocator.heap_index(allocations[0].device_memory().memory_type_index()),
+                allocations.iter().map(|allocation| allocation.size()).sum(),
+            ),
+            _ => unreachable!("Textures are always bound to allocated memory"),
+        };
+        memory_allocator.record_allocation(heap_index, size);
+
+        Ok(VwTexture {
+            image,
+            image_view,
+            sampler,
+            heap_index,
+            size,
+            memory_allocator,
+        })
+    }
+
+    pub fn image_view(&self) -> Arc<ImageView> {
+        self.image_view.clone()
+    }
+
+    pub fn sampler(&self) -> Arc<Sampler> {
+        self.sampler.clone()
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.image.mip_levels()
+    }
+}
+
+impl Drop for VwTexture {
+    fn drop(&mut self) {
+        self.memory_allocator
+            .record_free(self.heap_index, self.size);
+    }
+}
+
+/// The size of mip `level`, which halves each level but never drops below one texel.
+fn mip_extent(data: &VwTextureData, level: u32) -> [u32; 2] {
+    [(data.width >> level).max(1), (data.height >> level).max(1)]
+}
+
+fn create_sampler(
+    device: &VwDevice,
+    create_info: &VwSamplerCreateInfo,
+) -> Result<Arc<Sampler>, VwError> {
+    let anisotropy = if device.enabled_features().sampler_anisotropy {
+        let max_sampler_anisotropy = device
+            .logical_device()
+            .physical_device()
+            .properties()
+            .max_sampler_anisotropy;
+        create_info
+            .max_anisotropy
+            .map(|anisotropy| anisotropy.clamp(1.0, max_sampler_anisotropy))
+    } else {
+        None
+    };
+
+    Ok(Sampler::new(
+        device.logical_device(),
+        SamplerCreateInfo {
+            mag_filter: create_info.mag_filter,
+            min_filter: create_info.min_filter,
+            mipmap_mode: create_info.mipmap_mode,
+            address_mode: create_info.address_mode,
+            anisotropy,
+            lod: 0.0..=LOD_CLAMP_NONE,
+            ..Default::default()
+        },
+    )?)
+}
@@ -1,98 +1,877 @@
-use std::sync::Arc;
+use std::{ffi::CString, sync::Arc};
 
-use anyhow::bail;
 use ash::vk;
+use vulkano::format::Format;
 
-use crate::vw_engine2::vw_device2::VwDevice2;
+use crate::{
+    vw_engine::{
+        VkWizardEngineCreateInfo, VwColorOutput, VwHeapStats, VwPreRotation, VwPresentPolicy,
+        VwPushConstants, VwTextureCreateInfo, VwTextureData, VwVertex, VwVertexLayout,
+    },
+    vw_engine2::{
+        vw_buffer2::VwStagingRing2,
+        vw_debug2::VwDebugMessenger2,
+        vw_depth2::VwDepthBuffer2,
+        vw_device2::VwDevice2,
+        vw_mesh2::VwMesh2,
+        vw_pipeline2::VwPipeline2,
+        vw_present_ownership2::VwPresentOwnership2,
+        vw_swapchain2::{VwSwapchain2, VwSwapchain2CreateInfo},
+    },
+    vw_math::{self, VwMat4},
+    vw_renderer::{VALIDATION_LAYER, VwRenderer, check_extension_support, is_layer_available},
+    vw_scene::VwMaterial,
+    vw_window::VwWindow,
+};
 
+mod vw_allocator2;
+mod vw_buffer2;
+mod vw_debug2;
+mod vw_depth2;
 mod vw_device2;
+mod vw_mesh2;
+mod vw_pipeline2;
+mod vw_present_ownership2;
+mod vw_swapchain2;
 
+/// The raw ash counterpart of `VkWizardEngine`, kept around to compare overhead and correctness
+/// between the two implementations. Only windowed rendering is supported, and meshes are drawn
+/// unlit: materials only contribute their base color factor and textures are ignored.
 pub struct VwEngine2 {
     _entry: ash::Entry,
     instance: Arc<ash::Instance>,
+    debug_messenger: Option<VwDebugMessenger2>,
     device: VwDevice2,
+    swapchain: VwSwapchain2,
+    depth_buffer: VwDepthBuffer2,
+    pipeline: VwPipeline2,
+    window: VwWindow,
+
+    vertex_layout: VwVertexLayout,
+    staging: VwStagingRing2,
+    meshes: Vec<VwMesh2>,
+    /// The base color factor of each material, baked into the vertex colors.
+    material_colors: Vec<[f32; 4]>,
+    /// Textures aren't uploaded, only counted to hand out indices.
+    texture_count: usize,
+    view_projection: VwMat4,
+
+    command_pool: vk::CommandPool,
+    frames: Vec<VwFrame2>,
+    /// Signaled when rendering to a swapchain image finishes. Indexed by image rather than by
+    /// frame, since presentation may still wait on it after the frame slot is reused.
+    render_finished: Vec<vk::Semaphore>,
+    /// Only set when the swapchain is exclusive across split graphics and present families.
+    present_ownership: Option<VwPresentOwnership2>,
+    current_frame: usize,
+    recreate_swapchain: bool,
+}
+
+/// Size of the staging ring meshes are uploaded through. Larger uploads are split up.
+const STAGING_RING_SIZE: vk::DeviceSize = 16 * 1024 * 1024;
+
+/// Per frame-in-flight resources.
+struct VwFrame2 {
+    command_buffer: vk::CommandBuffer,
+    image_available: vk::Semaphore,
+    in_flight: vk::Fence,
 }
 
 impl VwEngine2 {
-    pub fn new() -> anyhow::Result<Self> {
+    pub fn new(create_info: VkWizardEngineCreateInfo) -> anyhow::Result<Self> {
         use ash::Entry;
+
+        if create_info.headless.is_some() {
+            anyhow::bail!("Headless rendering is not supported by the ash backend");
+        }
+
+        let window = VwWindow::new(create_info.window.clone())?;
         let entry = Entry::linked();
 
-        let instance = unsafe { create_instance(&entry)? };
+        let (instance, color_output, mut debug_messenger) = unsafe {
+            create_instance(
+                &entry,
+                &window.required_instance_extensions()?,
+                &create_info,
+            )?
+        };
+        if let Some(debug_messenger) = &mut debug_messenger {
+            debug_messenger.attach(&entry, &instance)?;
+        }
         let instance = Arc::new(instance);
-        let device = VwDevice2::new(instance.clone())?;
+
+        let surface = window.create_raw_surface(instance.handle())?;
+        let device = VwDevice2::new(
+            &entry,
+            instance.clone(),
+            surface,
+            &create_info.device_selection,
+            create_info.simulate_split_present_family,
+        )?;
+        let swapchain = VwSwapchain2::new(
+            &device,
+            window.drawable_extent(),
+            &VwSwapchain2CreateInfo {
+                present_policy: create_info.present_policy,
+                image_count: create_info.swapchain_image_count,
+                sharing: create_info.swapchain_sharing,
+                color_output,
+                pre_rotation: create_info.pre_rotation,
+                transparent: create_info.transparent,
+            },
+        )?;
+        log::info!(
+            "Swapchain has {} images, {:?} output in {:?}",
+            swapchain.image_count(),
+            swapchain.color_output(),
+            swapchain.color_space()
+        );
+        let present_ownership = if swapchain.needs_ownership_transfer(&device) {
+            log::info!("Transferring swapchain image ownership to the present family");
+            Some(VwPresentOwnership2::new(&device, &swapchain)?)
+        } else {
+            None
+        };
+        let depth_buffer = VwDepthBuffer2::new(&device, swapchain.extent())?;
+        let vertex_layout = VwVertexLayout::default();
+        let pipeline = VwPipeline2::new(
+            device.device(),
+            create_info.shader_code,
+            swapchain.surface_format(),
+            depth_buffer.format(),
+            &vertex_layout,
+        )?;
+
+        let mut staging = VwStagingRing2::new(&device, STAGING_RING_SIZE)?;
+        let meshes = vec![VwMesh2::new(
+            &device,
+            &mut staging,
+            &vertex_layout,
+            &VwVertex::fullscreen_triangle(),
+            &[0, 1, 2],
+        )?];
+        staging.flush(&device)?;
+
+        let command_pool = unsafe {
+            device.device().create_command_pool(
+                &vk::CommandPoolCreateInfo::default()
+                    .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                    .queue_family_index(device.graphics_family()),
+                None,
+            )
+        }?;
+        let frames = create_frames(
+            device.device(),
+            command_pool,
+            create_info.frames_in_flight.max(1),
+        )?;
+        let render_finished = create_semaphores(device.device(), swapchain.images().len())?;
+
+        if let Some(debug_messenger) = &debug_messenger {
+            debug_messenger.check();
+        }
 
         Ok(VwEngine2 {
             _entry: entry,
             instance,
+            debug_messenger,
             device,
+            swapchain,
+            depth_buffer,
+            pipeline,
+            window,
+
+            vertex_layout,
+            staging,
+            meshes,
+            material_colors: vec![],
+            texture_count: 0,
+            view_projection: vw_math::IDENTITY,
+
+            command_pool,
+            frames,
+            render_finished,
+            present_ownership,
+            current_frame: 0,
+            recreate_swapchain: false,
         })
     }
+
+    fn record_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) -> anyhow::Result<()> {
+        let device = self.device.device();
+        let image = self.swapchain.images()[image_index];
+        let image_view = self.swapchain.image_views()[image_index];
+        let extent = self.swapchain.extent();
+        // Read every frame, the transform can change whenever the swapchain is recreated
+        let color_output = self.swapchain.color_output();
+        let push_constants = VwPushConstants {
+            view_projection: vw_math::mul(
+                &vw_math::rotate_z(self.swapchain.pre_rotation().degrees()),
+                &self.view_projection,
+            ),
+            eye: vw_math::eye(&self.view_projection),
+            color_output: color_output.shader_id(),
+            encode_srgb: Format::try_from(self.swapchain.surface_format())
+                .is_ok_and(|format| color_output.needs_srgb_encoding(format))
+                .into(),
+        };
+        // Transparent windows show the desktop wherever nothing was drawn
+        let clear_alpha = if self.swapchain.is_transparent() {
+            0.0
+        } else {
+            1.0
+        };
+
+        unsafe {
+            device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+            device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+
+            transition_image(
+                device,
+                command_buffer,
+                image,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                None,
+            );
+            transition_depth_image(device, command_buffer, self.depth_buffer.image());
+
+            let color_attachments = [vk::RenderingAttachmentInfo::default()
+                .image_view(image_view)
+                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .clear_value(vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: [0.0, 0.0, 0.0, clear_alpha],
+                    },
+                })];
+            // Only needed while the frame is being drawn
+            let depth_attachment = vk::RenderingAttachmentInfo::default()
+                .image_view(self.depth_buffer.image_view())
+                .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .clear_value(vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 1.0,
+                        stencil: 0,
+                    },
+                });
+            let rendering_info = vk::RenderingInfo::default()
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D::default(),
+                    extent,
+                })
+                .layer_count(1)
+                .color_attachments(&color_attachments)
+                .depth_attachment(&depth_attachment);
+
+            device.cmd_begin_rendering(command_buffer, &rendering_info);
+
+            let viewport = vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: extent.width as f32,
+                height: extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            };
+            let scissor = vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent,
+            };
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline(),
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline.layout(),
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                push_constants.as_bytes(),
+            );
+
+            for mesh in self.meshes.iter() {
+                mesh.draw(device, command_buffer);
+            }
+
+            device.cmd_end_rendering(command_buffer);
+
+            // With exclusive sharing across split families this is the release half of the
+            // ownership transfer, the present queue records the acquire half
+            let queue_families = self
+                .present_ownership
+                .is_some()
+                .then(|| (self.device.graphics_family(), self.device.present_family()));
+            transition_image(
+                device,
+                command_buffer,
+                image,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                queue_families,
+            );
+
+            device.end_command_buffer(command_buffer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds the swapchain at the window's current drawable size. Returns `false` while the
+    /// window has no drawable area, in which case the swapchain is kept as is.
+    fn recreate_swapchain(&mut self) -> anyhow::Result<bool> {
+        let drawable_extent = self.window.drawable_extent();
+        if drawable_extent.contains(&0) {
+            return Ok(false);
+        }
+
+        // The old images may still be in use by frames in flight
+        unsafe { self.device.device().device_wait_idle() }?;
+        if !self.swapchain.recreate(&self.device, drawable_extent)? {
+            return Ok(false);
+        }
+        self.depth_buffer
+            .recreate(&self.device, self.swapchain.extent())?;
+
+        // The image count can change with the swapchain
+        if self.render_finished.len() != self.swapchain.images().len() {
+            unsafe { destroy_semaphores(self.device.device(), &mut self.render_finished) };
+            self.render_finished =
+                create_semaphores(self.device.device(), self.swapchain.images().len())?;
+        }
+        if let Some(present_ownership) = &mut self.present_ownership {
+            present_ownership.record(&self.device, &self.swapchain)?;
+        }
+
+        self.recreate_swapchain = false;
+        Ok(true)
+    }
 }
 
-unsafe fn create_instance(entry: &ash::Entry) -> anyhow::Result<ash::Instance> {
-    use ash::khr;
+impl VwRenderer for VwEngine2 {
+    fn window(&self) -> Option<&VwWindow> {
+        Some(&self.window)
+    }
 
-    check_extension_support(&entry)?;
+    /// Renders one frame into the next swapchain image.
+    fn draw_frame(&mut self) -> anyhow::Result<()> {
+        if self.recreate_swapchain && !self.recreate_swapchain()? {
+            // The surface has no area right now, skip rendering until it does
+            return Ok(());
+        }
 
-    let app_info = vk::ApplicationInfo::default()
-        .application_name(c"VkWizard Application")
-        .application_version(vk::make_api_version(0, 1, 0, 0))
-        .engine_name(c"VkWizard Engine")
-        .engine_version(vk::make_api_version(0, 1, 0, 0))
-        .api_version(vk::API_VERSION_1_3);
+        let device = self.device.device();
+        let frame = &self.frames[self.current_frame];
 
-    // Required extensions by SDL2 for Vulkan surface creation on Windows
-    let extension_names = vec![
-        khr::surface::NAME.as_ptr(),
-        khr::win32_surface::NAME.as_ptr(),
-    ];
+        unsafe { device.wait_for_fences(&[frame.in_flight], true, u64::MAX) }?;
 
-    let mut enable_layers = vec![];
-    if cfg!(debug_assertions) {
-        enable_layers.push(c"VK_LAYER_KHRONOS_validation".as_ptr());
-        println!("Enabling validation layers. Please wait...");
+        let acquired = unsafe {
+            self.swapchain.loader().acquire_next_image(
+                self.swapchain.swapchain(),
+                u64::MAX,
+                frame.image_available,
+                vk::Fence::null(),
+            )
+        };
+        let (image_index, suboptimal) = match acquired {
+            Ok(acquired) => acquired,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.recreate_swapchain = true;
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        self.record_commands(frame.command_buffer, image_index as usize)?;
+
+        let wait_semaphores = [frame.image_available];
+        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let command_buffers = [frame.command_buffer];
+        let signal_semaphores = [self.render_finished[image_index as usize]];
+        let submit_info = vk::SubmitInfo::default()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+
+        // Only reset the fence right before the submission that signals it again, if recording
+        // fails the next wait on this frame would otherwise block forever
+        unsafe {
+            device.reset_fences(&[frame.in_flight])?;
+            device.queue_submit(
+                self.device.graphics_queue(),
+                &[submit_info],
+                frame.in_flight,
+            )
+        }?;
+
+        let present_wait_semaphores = match &self.present_ownership {
+            Some(present_ownership) => [present_ownership.submit(
+                &self.device,
+                image_index as usize,
+                signal_semaphores[0],
+            )?],
+            None => signal_semaphores,
+        };
+
+        let swapchains = [self.swapchain.swapchain()];
+        let image_indices = [image_index];
+        let present_info = vk::PresentInfoKHR::default()
+            .wait_semaphores(&present_wait_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices);
+
+        let presented = unsafe {
+            self.swapchain
+                .loader()
+                .queue_present(self.device.present_queue(), &present_info)
+        };
+        match presented {
+            // A suboptimal image was still presented, but the swapchain should be rebuilt
+            Ok(present_suboptimal) => {
+                if suboptimal || present_suboptimal {
+                    self.recreate_swapchain = true;
+                }
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.recreate_swapchain = true,
+            Err(err) => return Err(err.into()),
+        }
+
+        self.current_frame = (self.current_frame + 1) % self.frames.len();
+
+        if let Some(debug_messenger) = &self.debug_messenger {
+            debug_messenger.check();
+        }
+
+        Ok(())
     }
 
-    let instance_create_info = vk::InstanceCreateInfo::default()
-        .application_info(&app_info)
-        .enabled_extension_names(&extension_names)
-        .enabled_layer_names(&enable_layers);
+    fn add_texture(
+        &mut self,
+        data: &VwTextureData,
+        _create_info: &VwTextureCreateInfo,
+    ) -> anyhow::Result<usize> {
+        log::debug!(
+            "Ignoring a {}x{} texture, the ash backend draws unlit",
+            data.width,
+            data.height
+        );
+        self.texture_count += 1;
+        Ok(self.texture_count - 1)
+    }
 
-    let instance = unsafe { entry.create_instance(&instance_create_info, None) }?;
+    fn add_material(&mut self, material: &VwMaterial) -> anyhow::Result<usize> {
+        self.material_colors.push(material.base_color_factor);
+        Ok(self.material_colors.len() - 1)
+    }
 
-    Ok(instance)
+    fn add_mesh(
+        &mut self,
+        vertices: &[VwVertex],
+        indices: &[u32],
+        material: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let base_color = match material {
+            Some(index) => *self.material_colors.get(index).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Material {index} was never added, there are {}",
+                    self.material_colors.len()
+                )
+            })?,
+            None => [1.0; 4],
+        };
+        let vertices = vertices
+            .iter()
+            .map(|vertex| VwVertex {
+                color: [0, 1, 2, 3].map(|channel| vertex.color[channel] * base_color[channel]),
+                ..*vertex
+            })
+            .collect::<Vec<_>>();
+
+        let mesh = VwMesh2::new(
+            &self.device,
+            &mut self.staging,
+            &self.vertex_layout,
+            &vertices,
+            indices,
+        )?;
+        self.staging.flush(&self.device)?;
+        self.meshes.push(mesh);
+        Ok(())
+    }
+
+    fn clear_scene(&mut self) -> anyhow::Result<()> {
+        // Frames in flight may still be reading the buffers
+        self.wait_idle()?;
+        for mut mesh in self.meshes.drain(..) {
+            unsafe { mesh.destroy(&self.device) };
+        }
+        self.material_colors.clear();
+        self.texture_count = 0;
+        Ok(())
+    }
+
+    fn set_view_projection(&mut self, view_projection: VwMat4) {
+        self.view_projection = view_projection;
+    }
+
+    fn request_swapchain_recreate(&mut self) {
+        self.recreate_swapchain = true;
+    }
+
+    fn present_policy(&self) -> Option<VwPresentPolicy> {
+        Some(self.swapchain.present_policy())
+    }
+
+    fn set_present_policy(&mut self, present_policy: VwPresentPolicy) {
+        self.swapchain.set_present_policy(present_policy);
+        self.recreate_swapchain = true;
+    }
+
+    fn color_output(&self) -> Option<VwColorOutput> {
+        Some(self.swapchain.color_output())
+    }
+
+    fn pre_rotation(&self) -> VwPreRotation {
+        self.swapchain.pre_rotation()
+    }
+
+    fn heap_stats(&self) -> Vec<VwHeapStats> {
+        self.device.allocator().heap_stats().to_vec()
+    }
+
+    fn wait_idle(&mut self) -> anyhow::Result<()> {
+        unsafe { self.device.device().device_wait_idle() }?;
+        Ok(())
+    }
+}
+
+fn create_frames(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    frames_in_flight: usize,
+) -> anyhow::Result<Vec<VwFrame2>> {
+    let allocate_info = vk::CommandBufferAllocateInfo::default()
+        .command_pool(command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(frames_in_flight as u32);
+    let command_buffers = unsafe { device.allocate_command_buffers(&allocate_info) }?;
+
+    command_buffers
+        .into_iter()
+        .map(|command_buffer| unsafe {
+            // Fences start signaled so the first wait on each frame doesn't block forever
+            let fence_create_info =
+                vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
+
+            Ok(VwFrame2 {
+                command_buffer,
+                image_available: device
+                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?,
+                in_flight: device.create_fence(&fence_create_info, None)?,
+            })
+        })
+        .collect()
+}
+
+fn create_semaphores(device: &ash::Device, count: usize) -> anyhow::Result<Vec<vk::Semaphore>> {
+    (0..count)
+        .map(|_| Ok(unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) }?))
+        .collect()
+}
+
+unsafe fn destroy_semaphores(device: &ash::Device, semaphores: &mut Vec<vk::Semaphore>) {
+    for semaphore in semaphores.drain(..) {
+        unsafe { device.destroy_semaphore(semaphore, None) };
+    }
 }
 
-fn check_extension_support(entry: &ash::Entry) -> anyhow::Result<()> {
+/// Records a full pipeline barrier moving the swapchain image between layouts. The rendering
+/// only touches color attachments, so the stages and accesses are limited to those.
+/// `queue_families` turns it into the release half of an ownership transfer.
+unsafe fn transition_image(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    queue_families: Option<(u32, u32)>,
+) {
+    let (src_queue_family, dst_queue_family) =
+        queue_families.unwrap_or((vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED));
+
+    let (src_access_mask, dst_access_mask) = if new_layout == vk::ImageLayout::PRESENT_SRC_KHR {
+        (
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags::empty(),
+        )
+    } else {
+        (
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        )
+    };
+    let (src_stage, dst_stage) = if new_layout == vk::ImageLayout::PRESENT_SRC_KHR {
+        (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        )
+    } else {
+        (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        )
+    };
+
+    let barrier = vk::ImageMemoryBarrier::default()
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(src_queue_family)
+        .dst_queue_family_index(dst_queue_family)
+        .image(image)
+        .subresource_range(
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .level_count(1)
+                .layer_count(1),
+        );
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+    }
+}
+
+/// Records the barrier that makes the depth image ready for this frame's depth test. The
+/// contents are cleared anyway, so they are discarded, but the previous frame's depth writes
+/// have to finish before this frame's clear.
+unsafe fn transition_depth_image(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+) {
+    let depth_stages =
+        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+
+    let barrier = vk::ImageMemoryBarrier::default()
+        .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+        .dst_access_mask(
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        )
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                .level_count(1)
+                .layer_count(1),
+        );
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            depth_stages,
+            depth_stages,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+    }
+}
+
+/// Creates the instance, also returning the color output it allows, which is `Sdr` when
+/// `VK_EXT_swapchain_colorspace` is missing, and the debug messenger to attach when validation
+/// is on.
+unsafe fn create_instance(
+    entry: &ash::Entry,
+    window_extensions: &[&str],
+    create_info: &VkWizardEngineCreateInfo,
+) -> anyhow::Result<(ash::Instance, VwColorOutput, Option<VwDebugMessenger2>)> {
     use ash::khr;
 
-    let extensions = unsafe { entry.enumerate_instance_extension_properties(None) }?;
-    let mut has_surface = false;
-    let mut has_win32_surface = false;
+    let layers = unsafe { entry.enumerate_instance_layer_properties() }?
+        .iter()
+        .map(|layer| Ok(layer.layer_name_as_c_str()?.to_str()?.to_owned()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let validation = create_info.validation
+        && is_layer_available(layers.iter().map(String::as_str), VALIDATION_LAYER);
+    let validation_layer = CString::new(VALIDATION_LAYER)?;
+
+    // Required extensions by SDL2 for Vulkan surface creation on the current windowing system
+    let mut extensions = vec![khr::surface::NAME.to_str()?];
+    for name in window_extensions {
+        if !extensions.contains(name) {
+            extensions.push(*name);
+        }
+    }
+
+    let mut supported_extensions = unsafe { entry.enumerate_instance_extension_properties(None) }?;
+    // Layers can provide instance extensions of their own, VK_EXT_validation_features among them
+    if validation {
+        supported_extensions.extend(unsafe {
+            entry.enumerate_instance_extension_properties(Some(validation_layer.as_c_str()))
+        }?);
+    }
+    let supported_extensions = supported_extensions
+        .iter()
+        .map(|ext| Ok(ext.extension_name_as_c_str()?.to_str()?.to_owned()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    check_extension_support(supported_extensions.iter().map(String::as_str), &extensions)?;
+    let is_supported = |name: &str| {
+        supported_extensions
+            .iter()
+            .any(|supported| supported == name)
+    };
 
-    for ext in extensions {
-        if ext.extension_name_as_c_str()? == khr::surface::NAME {
-            has_surface = true;
+    let mut color_output = create_info.color_output;
+    if color_output.needs_colorspace_extension() {
+        let swapchain_colorspace = ash::ext::swapchain_colorspace::NAME.to_str()?;
+        if is_supported(swapchain_colorspace) {
+            extensions.push(swapchain_colorspace);
+        } else {
+            log::warn!(
+                "VK_EXT_swapchain_colorspace is not supported, HDR and wide-gamut output are off"
+            );
+            color_output = VwColorOutput::Sdr;
         }
-        if ext.extension_name_as_c_str()? == khr::win32_surface::NAME {
-            has_win32_surface = true;
+    }
+
+    let debug_utils = ash::ext::debug_utils::NAME.to_str()?;
+    let debug_messenger = (validation && is_supported(debug_utils))
+        .then(|| VwDebugMessenger2::new(create_info.panic_on_validation_error));
+    if debug_messenger.is_some() {
+        extensions.push(debug_utils);
+    } else if validation {
+        log::warn!("VK_EXT_debug_utils is not supported, validation messages won't be logged");
+    }
+
+    let mut enabled_validation_features = vec![];
+    if validation && !create_info.validation_features.is_empty() {
+        let validation_features = ash::ext::validation_features::NAME.to_str()?;
+        if is_supported(validation_features) {
+            extensions.push(validation_features);
+            // vulkano's enum shares the raw values of the Vulkan one
+            enabled_validation_features = create_info
+                .validation_features
+                .iter()
+                .map(|&feature| vk::ValidationFeatureEnableEXT::from_raw(feature as i32))
+                .collect();
+        } else {
+            log::warn!("VK_EXT_validation_features is not supported, ignoring validation features");
         }
     }
 
-    if !has_surface {
-        bail!("Required extension khr_surface is not supported");
+    let application_name = CString::new(create_info.application_name)?;
+    let application_version = create_info.application_version;
+    let max_api_version = create_info.max_api_version;
+    let app_info = vk::ApplicationInfo::default()
+        .application_name(&application_name)
+        .application_version(vk::make_api_version(
+            0,
+            application_version.major,
+            application_version.minor,
+            application_version.patch,
+        ))
+        .engine_name(c"VkWizard Engine")
+        .engine_version(vk::make_api_version(0, 1, 0, 0))
+        .api_version(vk::make_api_version(
+            0,
+            max_api_version.major,
+            max_api_version.minor,
+            max_api_version.patch,
+        ));
+
+    let extensions = extensions
+        .into_iter()
+        .map(CString::new)
+        .collect::<Result<Vec<_>, _>>()?;
+    let extension_names = extensions
+        .iter()
+        .map(|name| name.as_ptr())
+        .collect::<Vec<_>>();
+
+    let mut enable_layers = vec![];
+    if validation {
+        enable_layers.push(validation_layer.as_ptr());
+        log::info!("Enabling validation layers. Please wait...");
+    }
+
+    let mut instance_create_info = vk::InstanceCreateInfo::default()
+        .application_info(&app_info)
+        .enabled_extension_names(&extension_names)
+        .enabled_layer_names(&enable_layers);
+
+    // Covers instance creation and destruction, where the regular messenger doesn't exist
+    let mut debug_create_info = debug_messenger.as_ref().map(VwDebugMessenger2::create_info);
+    if let Some(debug_create_info) = &mut debug_create_info {
+        instance_create_info = instance_create_info.push_next(debug_create_info);
     }
-    if !has_win32_surface {
-        bail!("Required extension khr_win32_surface is not supported");
+    let mut validation_features = vk::ValidationFeaturesEXT::default()
+        .enabled_validation_features(&enabled_validation_features);
+    if !enabled_validation_features.is_empty() {
+        instance_create_info = instance_create_info.push_next(&mut validation_features);
     }
 
-    Ok(())
+    let instance = unsafe { entry.create_instance(&instance_create_info, None) }?;
+
+    Ok((instance, color_output, debug_messenger))
 }
 
 impl Drop for VwEngine2 {
     fn drop(&mut self) {
         unsafe {
+            let device = self.device.device();
+            let _ = device.device_wait_idle();
+
+            for frame in self.frames.drain(..) {
+                device.destroy_semaphore(frame.image_available, None);
+                device.destroy_fence(frame.in_flight, None);
+            }
+            destroy_semaphores(device, &mut self.render_finished);
+            if let Some(present_ownership) = &mut self.present_ownership {
+                present_ownership.destroy(device);
+            }
+            device.destroy_command_pool(self.command_pool, None);
+
+            for mut mesh in self.meshes.drain(..) {
+                mesh.destroy(&self.device);
+            }
+            self.staging.destroy(&self.device);
+            self.pipeline.destroy(device);
+            self.depth_buffer.destroy(&self.device);
+            self.swapchain.destroy(&self.device);
+            self.device.destroy();
+            if let Some(debug_messenger) = &mut self.debug_messenger {
+                debug_messenger.destroy();
+            }
             self.instance.destroy_instance(None);
         }
     }
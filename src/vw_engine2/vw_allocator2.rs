@@ -0,0 +1,344 @@
+use std::ffi::c_void;
+
+use ash::vk;
+
+use crate::vw_engine::VwHeapStats;
+
+/// Size of the blocks allocations are carved out of. Larger allocations get a block of their
+/// own.
+const BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// A range of device memory handed out by [`VwAllocator2`].
+pub struct VwAllocation2 {
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    memory_type_index: u32,
+    block_index: usize,
+    /// Start of the range in the block's persistent mapping, null when not host visible.
+    mapped: *mut u8,
+}
+
+impl VwAllocation2 {
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+
+    pub fn offset(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    /// The persistently mapped range, `None` for memory the host can't see.
+    pub fn mapped(&self) -> Option<*mut u8> {
+        (!self.mapped.is_null()).then_some(self.mapped)
+    }
+}
+
+struct VwMemoryBlock2 {
+    memory: vk::DeviceMemory,
+    mapped: *mut c_void,
+    /// Free ranges as `(offset, size)`, sorted by offset and never adjacent.
+    free: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+/// A first-fit sub-allocator for the ash backend, the counterpart of vulkano's
+/// `StandardMemoryAllocator`. Memory is allocated from the driver in large blocks per memory
+/// type, and host-visible blocks are mapped once for their whole lifetime.
+pub struct VwAllocator2 {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// Every allocation is aligned to this as well, so buffers and optimally tiled images can
+    /// share a block without aliasing.
+    granularity: vk::DeviceSize,
+    /// Blocks per memory type.
+    blocks: Vec<Vec<VwMemoryBlock2>>,
+    heap_stats: Vec<VwHeapStats>,
+}
+
+impl VwAllocator2 {
+    pub fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+
+        let heap_stats = memory_properties
+            .memory_heaps_as_slice()
+            .iter()
+            .enumerate()
+            .map(|(heap_index, heap)| VwHeapStats {
+                heap_index: heap_index as u32,
+                heap_size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                ..Default::default()
+            })
+            .collect();
+
+        VwAllocator2 {
+            memory_properties,
+            granularity: properties.limits.buffer_image_granularity,
+            blocks: (0..memory_properties.memory_type_count)
+                .map(|_| vec![])
+                .collect(),
+            heap_stats,
+        }
+    }
+
+    /// Allocates memory for `requirements` from a type with all of `required` flags, preferring
+    /// one that also has `preferred`.
+    pub fn allocate(
+        &mut self,
+        device: &ash::Device,
+        requirements: vk::MemoryRequirements,
+        required: vk::MemoryPropertyFlags,
+        preferred: vk::MemoryPropertyFlags,
+    ) -> anyhow::Result<VwAllocation2> {
+        let memory_type_index = self
+            .find_memory_type(requirements.memory_type_bits, required | preferred)
+            .or_else(|| self.find_memory_type(requirements.memory_type_bits, required))
+            .ok_or_else(|| anyhow::anyhow!("No memory type with {required:?}"))?;
+
+        let alignment = requirements.alignment.max(self.granularity);
+        let size = requirements.size.next_multiple_of(self.granularity);
+
+        let (block_index, offset) = match self.find_free_range(memory_type_index, size, alignment) {
+            Some(found) => found,
+            None => {
+                self.allocate_block(device, memory_type_index, size)?;
+                let block_index = self.blocks[memory_type_index as usize].len() - 1;
+                (block_index, 0)
+            }
+        };
+
+        let block = &mut self.blocks[memory_type_index as usize][block_index];
+        take_range(&mut block.free, offset, size);
+        let mapped = if block.mapped.is_null() {
+            std::ptr::null_mut()
+        } else {
+            unsafe { block.mapped.cast::<u8>().add(offset as usize) }
+        };
+        let memory = block.memory;
+
+        let heap_index = self.heap_index(memory_type_index);
+        let stats = &mut self.heap_stats[heap_index];
+        stats.allocation_count += 1;
+        stats.allocated_bytes += size;
+        stats.peak_allocated_bytes = stats.peak_allocated_bytes.max(stats.allocated_bytes);
+
+        Ok(VwAllocation2 {
+            memory,
+            offset,
+            size,
+            memory_type_index,
+            block_index,
+            mapped,
+        })
+    }
+
+    /// Returns the range to its block. Blocks are kept around for later allocations until the
+    /// allocator is destroyed.
+    pub fn free(&mut self, allocation: VwAllocation2) {
+        let block = &mut self.blocks[allocation.memory_type_index as usize][allocation.block_index];
+        give_back_range(&mut block.free, allocation.offset, allocation.size);
+
+        let heap_index = self.heap_index(allocation.memory_type_index);
+        let stats = &mut self.heap_stats[heap_index];
+        stats.allocation_count -= 1;
+        stats.allocated_bytes -= allocation.size;
+    }
+
+    pub fn heap_stats(&self) -> &[VwHeapStats] {
+        &self.heap_stats
+    }
+
+    /// Frees every block. All allocations must have been freed and the device must be idle.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for block in self.blocks.iter_mut().flat_map(|blocks| blocks.drain(..)) {
+            unsafe {
+                if !block.mapped.is_null() {
+                    device.unmap_memory(block.memory);
+                }
+                device.free_memory(block.memory, None);
+            }
+        }
+    }
+
+    fn heap_index(&self, memory_type_index: u32) -> usize {
+        self.memory_properties.memory_types[memory_type_index as usize].heap_index as usize
+    }
+
+    fn find_memory_type(&self, type_bits: u32, flags: vk::MemoryPropertyFlags) -> Option<u32> {
+        self.memory_properties
+            .memory_types_as_slice()
+            .iter()
+            .enumerate()
+            .position(|(index, memory_type)| {
+                type_bits & (1 << index) != 0 && memory_type.property_flags.contains(flags)
+            })
+            .map(|index| index as u32)
+    }
+
+    fn find_free_range(
+        &self,
+        memory_type_index: u32,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<(usize, vk::DeviceSize)> {
+        self.blocks[memory_type_index as usize]
+            .iter()
+            .enumerate()
+            .find_map(|(block_index, block)| {
+                block.free.iter().find_map(|&(free_offset, free_size)| {
+                    let offset = free_offset.next_multiple_of(alignment);
+                    (offset + size <= free_offset + free_size).then_some((block_index, offset))
+                })
+            })
+    }
+
+    fn allocate_block(
+        &mut self,
+        device: &ash::Device,
+        memory_type_index: u32,
+        min_size: vk::DeviceSize,
+    ) -> anyhow::Result<()> {
+        let size = min_size.max(BLOCK_SIZE);
+        let memory = unsafe {
+            device.allocate_memory(
+                &vk::MemoryAllocateInfo::default()
+                    .allocation_size(size)
+                    .memory_type_index(memory_type_index),
+                None,
+            )
+        }?;
+
+        let host_visible = self.memory_properties.memory_types[memory_type_index as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+        let mapped = if host_visible {
+            unsafe { device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) }?
+        } else {
+            std::ptr::null_mut()
+        };
+
+        log::debug!(
+            "Allocated a {} MiB block of memory type {memory_type_index}",
+            size >> 20
+        );
+        self.blocks[memory_type_index as usize].push(VwMemoryBlock2 {
+            memory,
+            mapped,
+            free: vec![(0, size)],
+        });
+
+        Ok(())
+    }
+}
+
+/// Removes `offset..offset + size` from the free range that contains it, splitting the range
+/// when the allocation is in its middle.
+fn take_range(
+    free: &mut Vec<(vk::DeviceSize, vk::DeviceSize)>,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+) {
+    let index = free
+        .iter()
+        .position(|&(free_offset, free_size)| {
+            free_offset <= offset && offset + size <= free_offset + free_size
+        })
+        .expect("The range was found free");
+    let (free_offset, free_size) = free.remove(index);
+
+    let after = (offset + size, free_offset + free_size - offset - size);
+    if after.1 > 0 {
+        free.insert(index, after);
+    }
+    let before = (free_offset, offset - free_offset);
+    if before.1 > 0 {
+        free.insert(index, before);
+    }
+}
+
+/// Inserts a range back into the free list, merging it with its neighbors.
+fn give_back_range(
+    free: &mut Vec<(vk::DeviceSize, vk::DeviceSize)>,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+) {
+    let index = free.partition_point(|&(free_offset, _)| free_offset < offset);
+    free.insert(index, (offset, size));
+
+    if index + 1 < free.len() && free[index].0 + free[index].1 == free[index + 1].0 {
+        free[index].1 += free.remove(index + 1).1;
+    }
+    if index > 0 && free[index - 1].0 + free[index - 1].1 == free[index].0 {
+        free[index - 1].1 += free.remove(index).1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_whole_range() {
+        let mut free = vec![(0, 256)];
+        take_range(&mut free, 0, 256);
+        assert_eq!(free, []);
+    }
+
+    #[test]
+    fn take_from_start_and_end() {
+        let mut free = vec![(0, 256)];
+        take_range(&mut free, 0, 64);
+        assert_eq!(free, [(64, 192)]);
+
+        take_range(&mut free, 192, 64);
+        assert_eq!(free, [(64, 128)]);
+    }
+
+    #[test]
+    fn take_from_middle_splits() {
+        let mut free = vec![(0, 64), (128, 256), (512, 64)];
+        take_range(&mut free, 192, 64);
+        assert_eq!(free, [(0, 64), (128, 64), (256, 128), (512, 64)]);
+    }
+
+    #[test]
+    fn give_back_merges_with_neighbors() {
+        let mut free = vec![(0, 64), (128, 64)];
+        give_back_range(&mut free, 64, 64);
+        assert_eq!(free, [(0, 192)]);
+    }
+
+    #[test]
+    fn give_back_merges_one_side() {
+        let mut free = vec![(0, 64), (256, 64)];
+        give_back_range(&mut free, 64, 64);
+        assert_eq!(free, [(0, 128), (256, 64)]);
+
+        give_back_range(&mut free, 192, 64);
+        assert_eq!(free, [(0, 128), (192, 128)]);
+    }
+
+    #[test]
+    fn give_back_keeps_order() {
+        let mut free = vec![(128, 64)];
+        give_back_range(&mut free, 0, 64);
+        give_back_range(&mut free, 256, 64);
+        assert_eq!(free, [(0, 64), (128, 64), (256, 64)]);
+    }
+
+    #[test]
+    fn take_and_give_back_round_trip() {
+        let mut free = vec![(0, 1024)];
+        let ranges = [(0, 256), (256, 128), (512, 256), (384, 64)];
+        for (offset, size) in ranges {
+            take_range(&mut free, offset, size);
+        }
+        assert_eq!(free, [(448, 64), (768, 256)]);
+
+        for (offset, size) in ranges.into_iter().rev() {
+            give_back_range(&mut free, offset, size);
+        }
+        assert_eq!(free, [(0, 1024)]);
+    }
+}
@@ -0,0 +1,239 @@
+use ash::vk;
+
+use crate::{
+    vw_engine::VwBufferUsage,
+    vw_engine2::{vw_allocator2::VwAllocation2, vw_device2::VwDevice2},
+};
+
+/// A buffer sub-allocated from the device's [`VwAllocator2`](super::vw_allocator2::VwAllocator2).
+/// Has to be destroyed explicitly before the device.
+pub struct VwBuffer2 {
+    buffer: vk::Buffer,
+    allocation: Option<VwAllocation2>,
+    size: vk::DeviceSize,
+    usage: VwBufferUsage,
+}
+
+impl VwBuffer2 {
+    pub fn new(
+        device: &VwDevice2,
+        usage: VwBufferUsage,
+        size: vk::DeviceSize,
+    ) -> anyhow::Result<Self> {
+        let ash_device = device.device();
+        let buffer = unsafe {
+            ash_device.create_buffer(
+                &vk::BufferCreateInfo::default()
+                    .size(size)
+                    .usage(usage.buffer_usage().into())
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                None,
+            )
+        }?;
+
+        let (required, preferred) = memory_flags(usage);
+        let requirements = unsafe { ash_device.get_buffer_memory_requirements(buffer) };
+        let allocation = device
+            .allocator()
+            .allocate(ash_device, requirements, required, preferred);
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { ash_device.destroy_buffer(buffer, None) };
+                return Err(err);
+            }
+        };
+        unsafe { ash_device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset()) }?;
+
+        Ok(VwBuffer2 {
+            buffer,
+            allocation: Some(allocation),
+            size,
+            usage,
+        })
+    }
+
+    /// Copies `data` into the buffer at `offset` through the persistent mapping. The memory is
+    /// host coherent, so no flush is needed, but the GPU must not be reading the range.
+    pub fn write(&self, offset: vk::DeviceSize, data: &[u8]) -> anyhow::Result<()> {
+        let Some(mapped) = self.allocation.as_ref().and_then(VwAllocation2::mapped) else {
+            anyhow::bail!("A {:?} buffer is not host visible", self.usage);
+        };
+        anyhow::ensure!(
+            offset + data.len() as vk::DeviceSize <= self.size,
+            "Writing {} bytes at {offset} overflows a {} byte buffer",
+            data.len(),
+            self.size
+        );
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), mapped.add(offset as usize), data.len());
+        }
+        Ok(())
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// Destroys the buffer and returns its memory to the allocator. The GPU must be done with it.
+    pub unsafe fn destroy(&mut self, device: &VwDevice2) {
+        if let Some(allocation) = self.allocation.take() {
+            unsafe { device.device().destroy_buffer(self.buffer, None) };
+            device.allocator().free(allocation);
+            self.buffer = vk::Buffer::null();
+        }
+    }
+}
+
+/// Required and preferred memory properties, matching the vulkano backend's memory type filters.
+fn memory_flags(usage: VwBufferUsage) -> (vk::MemoryPropertyFlags, vk::MemoryPropertyFlags) {
+    let host_visible =
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+
+    match usage {
+        VwBufferUsage::Vertex | VwBufferUsage::Index | VwBufferUsage::Storage => (
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::MemoryPropertyFlags::empty(),
+        ),
+        VwBufferUsage::Uniform => (host_visible, vk::MemoryPropertyFlags::DEVICE_LOCAL),
+        VwBufferUsage::Staging => (host_visible, vk::MemoryPropertyFlags::empty()),
+    }
+}
+
+/// Uploads data to device-local buffers through a fixed-size staging buffer, the counterpart of
+/// the vulkano backend's `VwStagingRing`. Copies are recorded into one command buffer on the
+/// graphics queue and submitted on [`flush`](Self::flush), or earlier when the ring runs full.
+pub struct VwStagingRing2 {
+    staging: VwBuffer2,
+    head: vk::DeviceSize,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    recording: bool,
+}
+
+impl VwStagingRing2 {
+    pub fn new(device: &VwDevice2, size: vk::DeviceSize) -> anyhow::Result<Self> {
+        let ash_device = device.device();
+        let staging = VwBuffer2::new(device, VwBufferUsage::Staging, size)?;
+
+        let command_pool = unsafe {
+            ash_device.create_command_pool(
+                &vk::CommandPoolCreateInfo::default()
+                    .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                    .queue_family_index(device.graphics_family()),
+                None,
+            )
+        }?;
+        let command_buffer = unsafe {
+            ash_device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1),
+            )
+        }?[0];
+        let fence = unsafe { ash_device.create_fence(&vk::FenceCreateInfo::default(), None) }?;
+
+        Ok(VwStagingRing2 {
+            staging,
+            head: 0,
+            command_pool,
+            command_buffer,
+            fence,
+            recording: false,
+        })
+    }
+
+    /// Records a copy of `data` into `dst` at `dst_offset`. Data larger than the ring is split
+    /// into several copies.
+    pub fn upload(
+        &mut self,
+        device: &VwDevice2,
+        dst: &VwBuffer2,
+        dst_offset: vk::DeviceSize,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        let capacity = self.staging.size();
+        let mut uploaded = 0;
+
+        while uploaded < data.len() {
+            if self.head == capacity {
+                self.flush(device)?;
+            }
+
+            let chunk_size = (data.len() - uploaded).min((capacity - self.head) as usize);
+            self.staging
+                .write(self.head, &data[uploaded..uploaded + chunk_size])?;
+
+            if !self.recording {
+                unsafe {
+                    device.device().begin_command_buffer(
+                        self.command_buffer,
+                        &vk::CommandBufferBeginInfo::default()
+                            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                    )
+                }?;
+                self.recording = true;
+            }
+
+            let region = vk::BufferCopy::default()
+                .src_offset(self.head)
+                .dst_offset(dst_offset + uploaded as vk::DeviceSize)
+                .size(chunk_size as vk::DeviceSize);
+            unsafe {
+                device.device().cmd_copy_buffer(
+                    self.command_buffer,
+                    self.staging.buffer(),
+                    dst.buffer(),
+                    &[region],
+                );
+            }
+
+            self.head += chunk_size as vk::DeviceSize;
+            uploaded += chunk_size;
+        }
+
+        Ok(())
+    }
+
+    /// Submits the recorded copies and waits for them, after which the destination buffers can
+    /// be used and the whole ring is free again.
+    pub fn flush(&mut self, device: &VwDevice2) -> anyhow::Result<()> {
+        let ash_device = device.device();
+
+        if self.recording {
+            self.recording = false;
+            let command_buffers = [self.command_buffer];
+            unsafe {
+                ash_device.end_command_buffer(self.command_buffer)?;
+                ash_device.queue_submit(
+                    device.graphics_queue(),
+                    &[vk::SubmitInfo::default().command_buffers(&command_buffers)],
+                    self.fence,
+                )?;
+                ash_device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+                ash_device.reset_fences(&[self.fence])?;
+            }
+        }
+
+        self.head = 0;
+        Ok(())
+    }
+
+    /// Destroys the staging buffer and the command pool. Pending copies are dropped, so flush
+    /// first.
+    pub unsafe fn destroy(&mut self, device: &VwDevice2) {
+        unsafe {
+            let ash_device = device.device();
+            ash_device.destroy_fence(self.fence, None);
+            ash_device.destroy_command_pool(self.command_pool, None);
+            self.staging.destroy(device);
+        }
+    }
+}
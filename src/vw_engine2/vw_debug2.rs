@@ -0,0 +1,139 @@
+use std::{
+    borrow::Cow,
+    ffi::{CStr, c_void},
+    slice,
+};
+
+use ash::vk;
+use log::Level;
+
+use crate::vw_engine::{VwDebugLog, VwDebugMessage};
+
+/// The ash counterpart of `VwDebugMessenger`. Messages go through the same [`VwDebugLog`], so
+/// both backends format them identically and share the panic policy.
+pub struct VwDebugMessenger2 {
+    /// Boxed so the callback's user data pointer stays valid when the messenger moves.
+    debug_log: Box<VwDebugLog>,
+    loader: Option<ash::ext::debug_utils::Instance>,
+    messenger: vk::DebugUtilsMessengerEXT,
+}
+
+impl VwDebugMessenger2 {
+    pub fn new(panic_on_error: bool) -> Self {
+        VwDebugMessenger2 {
+            debug_log: Box::new(VwDebugLog::new(panic_on_error)),
+            loader: None,
+            messenger: vk::DebugUtilsMessengerEXT::null(),
+        }
+    }
+
+    /// Create info for the instance's `pNext` chain, which covers instance creation and
+    /// destruction where a regular messenger can't exist yet.
+    pub fn create_info(&self) -> vk::DebugUtilsMessengerCreateInfoEXT<'static> {
+        vk::DebugUtilsMessengerCreateInfoEXT::default()
+            .message_severity(
+                vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            )
+            .message_type(
+                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                    | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            )
+            .pfn_user_callback(Some(debug_callback))
+            .user_data(&*self.debug_log as *const VwDebugLog as *mut c_void)
+    }
+
+    /// Installs the messenger for the lifetime of the instance.
+    pub fn attach(&mut self, entry: &ash::Entry, instance: &ash::Instance) -> anyhow::Result<()> {
+        let loader = ash::ext::debug_utils::Instance::new(entry, instance);
+        self.messenger = unsafe { loader.create_debug_utils_messenger(&self.create_info(), None) }?;
+        self.loader = Some(loader);
+        Ok(())
+    }
+
+    /// Panics if a validation error was reported and the messenger was asked to do so.
+    pub fn check(&self) {
+        self.debug_log.check();
+    }
+
+    /// Must be called before the instance is destroyed.
+    pub unsafe fn destroy(&mut self) {
+        if let Some(loader) = self.loader.take() {
+            unsafe { loader.destroy_debug_utils_messenger(self.messenger, None) };
+            self.messenger = vk::DebugUtilsMessengerEXT::null();
+        }
+    }
+}
+
+/// Converts the raw callback data and hands it to the [`VwDebugLog`] behind `user_data`. Only
+/// formats and logs, it never calls back into Vulkan.
+unsafe extern "system" fn debug_callback(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    user_data: *mut c_void,
+) -> vk::Bool32 {
+    // Both stay valid for the duration of the call, and the log outlives the messenger
+    let (debug_log, data) = unsafe { (&*(user_data as *const VwDebugLog), &*data) };
+
+    let objects = if data.object_count == 0 || data.p_objects.is_null() {
+        &[][..]
+    } else {
+        unsafe { slice::from_raw_parts(data.p_objects, data.object_count as usize) }
+    };
+    let object_names = objects
+        .iter()
+        .map(|object| unsafe { object.object_name_as_c_str() }.map(CStr::to_string_lossy))
+        .collect::<Vec<_>>();
+    let id_name = unsafe { data.message_id_name_as_c_str() }.map(CStr::to_string_lossy);
+    let message = unsafe { data.message_as_c_str() }
+        .map(CStr::to_string_lossy)
+        .unwrap_or(Cow::Borrowed(""));
+
+    debug_log.report(&VwDebugMessage {
+        level: severity_level(severity),
+        type_name: type_name(message_type),
+        id_name: id_name.as_deref(),
+        id_number: data.message_id_number,
+        message: &message,
+        objects: objects
+            .iter()
+            .zip(&object_names)
+            .map(|(object, name)| {
+                (
+                    format!("{:?}", object.object_type),
+                    object.object_handle,
+                    name.as_deref(),
+                )
+            })
+            .collect(),
+    });
+
+    // Returning true would abort the call that triggered the message
+    vk::FALSE
+}
+
+fn severity_level(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> Level {
+    if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        Level::Error
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        Level::Warn
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        Level::Info
+    } else {
+        Level::Trace
+    }
+}
+
+fn type_name(message_type: vk::DebugUtilsMessageTypeFlagsEXT) -> &'static str {
+    if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+        "validation"
+    } else if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+        "performance"
+    } else {
+        "general"
+    }
+}
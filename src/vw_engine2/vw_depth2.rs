@@ -0,0 +1,162 @@
+use ash::vk;
+
+use crate::vw_engine2::{vw_allocator2::VwAllocation2, vw_device2::VwDevice2};
+
+/// The depth attachment meshes are tested against, the counterpart of the vulkano backend's
+/// `VwDepthBuffer`. It has to match the swapchain's size, so it is recreated together with it,
+/// and has to be destroyed explicitly before the device.
+pub struct VwDepthBuffer2 {
+    image: vk::Image,
+    image_view: vk::ImageView,
+    allocation: Option<VwAllocation2>,
+    format: vk::Format,
+    extent: vk::Extent2D,
+}
+
+impl VwDepthBuffer2 {
+    pub fn new(device: &VwDevice2, extent: vk::Extent2D) -> anyhow::Result<Self> {
+        let format = choose_depth_format(device);
+        log::info!("Using depth format {format:?}");
+
+        let mut depth_buffer = VwDepthBuffer2 {
+            image: vk::Image::null(),
+            image_view: vk::ImageView::null(),
+            allocation: None,
+            format,
+            extent,
+        };
+        depth_buffer.create(device)?;
+        Ok(depth_buffer)
+    }
+
+    /// Replaces the image with one of `extent`, keeping the format the pipeline was built for.
+    /// The device must be idle.
+    pub fn recreate(&mut self, device: &VwDevice2, extent: vk::Extent2D) -> anyhow::Result<()> {
+        if extent != self.extent {
+            unsafe { self.destroy(device) };
+            self.extent = extent;
+            self.create(device)?;
+        }
+        Ok(())
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn image_view(&self) -> vk::ImageView {
+        self.image_view
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    /// Destroys the image and returns its memory to the allocator. The device must be idle.
+    pub unsafe fn destroy(&mut self, device: &VwDevice2) {
+        if let Some(allocation) = self.allocation.take() {
+            unsafe {
+                device.device().destroy_image_view(self.image_view, None);
+                device.device().destroy_image(self.image, None);
+            }
+            device.allocator().free(allocation);
+            self.image_view = vk::ImageView::null();
+            self.image = vk::Image::null();
+        }
+    }
+
+    fn create(&mut self, device: &VwDevice2) -> anyhow::Result<()> {
+        let ash_device = device.device();
+        let image = unsafe {
+            ash_device.create_image(
+                &vk::ImageCreateInfo::default()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(self.format)
+                    .extent(vk::Extent3D {
+                        width: self.extent.width,
+                        height: self.extent.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED),
+                None,
+            )
+        }?;
+
+        let requirements = unsafe { ash_device.get_image_memory_requirements(image) };
+        let allocation = device.allocator().allocate(
+            ash_device,
+            requirements,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::MemoryPropertyFlags::empty(),
+        );
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { ash_device.destroy_image(image, None) };
+                return Err(err);
+            }
+        };
+        let (memory, offset) = (allocation.memory(), allocation.offset());
+        self.image = image;
+        self.allocation = Some(allocation);
+
+        // From here on `destroy` cleans up after a failure
+        if let Err(err) = self.create_image_view(ash_device, memory, offset) {
+            unsafe { self.destroy(device) };
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    fn create_image_view(
+        &mut self,
+        ash_device: &ash::Device,
+        memory: vk::DeviceMemory,
+        offset: vk::DeviceSize,
+    ) -> anyhow::Result<()> {
+        unsafe { ash_device.bind_image_memory(self.image, memory, offset) }?;
+        self.image_view = unsafe {
+            ash_device.create_image_view(
+                &vk::ImageViewCreateInfo::default()
+                    .image(self.image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(self.format)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::default()
+                            .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                            .level_count(1)
+                            .layer_count(1),
+                    ),
+                None,
+            )
+        }?;
+
+        Ok(())
+    }
+}
+
+/// `D32_SFLOAT` for its precision when the device can render to it, otherwise `D16_UNORM`, which
+/// every device supports as a depth attachment.
+fn choose_depth_format(device: &VwDevice2) -> vk::Format {
+    let properties = unsafe {
+        device
+            .instance()
+            .get_physical_device_format_properties(device.physical_device(), vk::Format::D32_SFLOAT)
+    };
+
+    if properties
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    {
+        vk::Format::D32_SFLOAT
+    } else {
+        vk::Format::D16_UNORM
+    }
+}
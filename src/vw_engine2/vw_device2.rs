@@ -0,0 +1,337 @@
+use std::{
+    ffi::CStr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use ash::{khr, vk};
+
+use crate::{
+    vw_device_selection::{
+        VwDeviceCandidate, VwDeviceSelection, VwDeviceType, rank_candidates, select_candidate,
+    },
+    vw_engine2::vw_allocator2::VwAllocator2,
+    vw_error::VwError,
+    vw_queue_family::{VwQueueFamilySupport, pick_graphics_present_families},
+};
+
+pub struct VwDevice2 {
+    instance: Arc<ash::Instance>,
+    surface_loader: khr::surface::Instance,
+    surface: vk::SurfaceKHR,
+    physical_device: vk::PhysicalDevice,
+    device: ash::Device,
+    graphics_family: u32,
+    present_family: u32,
+    graphics_queue: vk::Queue,
+    present_queue: vk::Queue,
+    allocator: Mutex<VwAllocator2>,
+}
+
+pub struct VwSwapChainSupportDetails2 {
+    pub surface_capabilities: vk::SurfaceCapabilitiesKHR,
+    pub surface_formats: Vec<vk::SurfaceFormatKHR>,
+    pub present_modes: Vec<vk::PresentModeKHR>,
+}
+
+const REQUIRED_DEVICE_EXTENSIONS: [&CStr; 1] = [khr::swapchain::NAME];
+
+impl VwDevice2 {
+    /// Picks a physical device and creates the logical device. Takes ownership of `surface`,
+    /// which is destroyed together with the device.
+    pub fn new(
+        entry: &ash::Entry,
+        instance: Arc<ash::Instance>,
+        surface: vk::SurfaceKHR,
+        selection: &VwDeviceSelection,
+        simulate_split_present_family: bool,
+    ) -> anyhow::Result<Self> {
+        let surface_loader = khr::surface::Instance::new(entry, &instance);
+
+        let selection = VwDeviceSelection::resolve(selection);
+        let candidates = rank_physical_devices(&instance, &surface_loader, surface, &selection)?;
+        let physical_device = *select_candidate(&candidates)?;
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        log::info!(
+            "Selected Vulkan physical device: {:?}",
+            properties.device_name_as_c_str()?
+        );
+
+        let (graphics_family, present_family) = pick_graphics_present_queues(
+            &instance,
+            &surface_loader,
+            physical_device,
+            surface,
+            simulate_split_present_family,
+        )?;
+
+        let device =
+            create_logical_device(&instance, physical_device, graphics_family, present_family)?;
+        let graphics_queue = unsafe { device.get_device_queue(graphics_family, 0) };
+        let present_queue = unsafe { device.get_device_queue(present_family, 0) };
+        let allocator = Mutex::new(VwAllocator2::new(&instance, physical_device));
+
+        Ok(VwDevice2 {
+            instance,
+            surface_loader,
+            surface,
+            physical_device,
+            device,
+            graphics_family,
+            present_family,
+            graphics_queue,
+            present_queue,
+            allocator,
+        })
+    }
+
+    pub fn swap_chain_support(&self) -> anyhow::Result<VwSwapChainSupportDetails2> {
+        unsafe {
+            let surface_capabilities = self
+                .surface_loader
+                .get_physical_device_surface_capabilities(self.physical_device, self.surface)?;
+
+            let surface_formats = self
+                .surface_loader
+                .get_physical_device_surface_formats(self.physical_device, self.surface)?;
+
+            let present_modes = self
+                .surface_loader
+                .get_physical_device_surface_present_modes(self.physical_device, self.surface)?;
+
+            Ok(VwSwapChainSupportDetails2 {
+                surface_capabilities,
+                surface_formats,
+                present_modes,
+            })
+        }
+    }
+
+    pub fn instance(&self) -> &ash::Instance {
+        &self.instance
+    }
+
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.physical_device
+    }
+
+    pub fn device(&self) -> &ash::Device {
+        &self.device
+    }
+
+    pub fn surface(&self) -> vk::SurfaceKHR {
+        self.surface
+    }
+
+    pub fn graphics_family(&self) -> u32 {
+        self.graphics_family
+    }
+
+    pub fn present_family(&self) -> u32 {
+        self.present_family
+    }
+
+    pub fn graphics_queue(&self) -> vk::Queue {
+        self.graphics_queue
+    }
+
+    pub fn present_queue(&self) -> vk::Queue {
+        self.present_queue
+    }
+
+    /// The memory allocator every buffer on this device is sub-allocated from.
+    pub fn allocator(&self) -> MutexGuard<'_, VwAllocator2> {
+        self.allocator.lock().unwrap()
+    }
+
+    /// Destroys the allocator's memory, the logical device and the surface. Everything created
+    /// from the device has to be destroyed before calling this.
+    pub unsafe fn destroy(&mut self) {
+        unsafe {
+            self.allocator().destroy(&self.device);
+            self.device.destroy_device(None);
+            self.surface_loader.destroy_surface(self.surface, None);
+        }
+    }
+}
+
+/// Lists everything that keeps the device from being used, an empty list means it is suitable.
+/// Mirrors the vulkano backend's checks.
+fn rejection_reasons(
+    instance: &ash::Instance,
+    surface_loader: &khr::surface::Instance,
+    physical_device: vk::PhysicalDevice,
+    surface: vk::SurfaceKHR,
+) -> anyhow::Result<Vec<String>> {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+
+    let mut reasons = vec![];
+
+    if properties.api_version < vk::API_VERSION_1_3 {
+        reasons.push(format!(
+            "Vulkan {}.{}.{} is older than the required 1.3",
+            vk::api_version_major(properties.api_version),
+            vk::api_version_minor(properties.api_version),
+            vk::api_version_patch(properties.api_version)
+        ));
+    }
+
+    let mut features13 = vk::PhysicalDeviceVulkan13Features::default();
+    let mut features11 = vk::PhysicalDeviceVulkan11Features::default();
+    let mut features = vk::PhysicalDeviceFeatures2::default()
+        .push_next(&mut features13)
+        .push_next(&mut features11);
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+    if features13.dynamic_rendering == vk::FALSE {
+        reasons.push("missing feature dynamic_rendering".to_string());
+    }
+    if features11.shader_draw_parameters == vk::FALSE {
+        reasons.push("missing feature shader_draw_parameters".to_string());
+    }
+
+    let extensions = unsafe { instance.enumerate_device_extension_properties(physical_device) }?;
+    for required in REQUIRED_DEVICE_EXTENSIONS {
+        let supported = extensions.iter().any(|ext| {
+            ext.extension_name_as_c_str()
+                .is_ok_and(|name| name == required)
+        });
+        if !supported {
+            reasons.push(format!("missing extension {}", required.to_string_lossy()));
+        }
+    }
+
+    match pick_graphics_present_queues(instance, surface_loader, physical_device, surface, false) {
+        Ok(_) => {}
+        Err(err) => match err.downcast_ref::<VwError>() {
+            Some(VwError::QueueFamilyNotFound(queue)) => {
+                reasons.push(format!("no {queue} queue family"));
+            }
+            _ => reasons.push(err.to_string()),
+        },
+    }
+
+    Ok(reasons)
+}
+
+/// Evaluates every physical device against the requirements and `selection`, see
+/// [`rank_candidates`].
+fn rank_physical_devices(
+    instance: &ash::Instance,
+    surface_loader: &khr::surface::Instance,
+    surface: vk::SurfaceKHR,
+    selection: &VwDeviceSelection,
+) -> anyhow::Result<Vec<VwDeviceCandidate<vk::PhysicalDevice>>> {
+    let candidates = unsafe { instance.enumerate_physical_devices() }?
+        .into_iter()
+        .enumerate()
+        .map(|(index, physical_device)| {
+            let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+
+            Ok(VwDeviceCandidate {
+                index,
+                name: properties
+                    .device_name_as_c_str()?
+                    .to_string_lossy()
+                    .into_owned(),
+                device_type: device_type(properties.device_type),
+                vendor_id: properties.vendor_id,
+                device_id: properties.device_id,
+                max_image_dimension: properties.limits.max_image_dimension2_d,
+                score: None,
+                rejection_reasons: rejection_reasons(
+                    instance,
+                    surface_loader,
+                    physical_device,
+                    surface,
+                )?,
+                physical_device,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(rank_candidates(candidates, selection))
+}
+
+fn device_type(device_type: vk::PhysicalDeviceType) -> VwDeviceType {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => VwDeviceType::DiscreteGpu,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => VwDeviceType::IntegratedGpu,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => VwDeviceType::VirtualGpu,
+        vk::PhysicalDeviceType::CPU => VwDeviceType::Cpu,
+        _ => VwDeviceType::Other,
+    }
+}
+
+fn pick_graphics_present_queues(
+    instance: &ash::Instance,
+    surface_loader: &khr::surface::Instance,
+    physical_device: vk::PhysicalDevice,
+    surface: vk::SurfaceKHR,
+    prefer_split: bool,
+) -> anyhow::Result<(u32, u32)> {
+    let families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) }
+        .iter()
+        .enumerate()
+        .map(|(index, qfp)| {
+            Ok(VwQueueFamilySupport {
+                graphics: qfp.queue_flags.contains(vk::QueueFlags::GRAPHICS),
+                present: unsafe {
+                    surface_loader.get_physical_device_surface_support(
+                        physical_device,
+                        index as u32,
+                        surface,
+                    )
+                }?,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // Windowed rendering always has a surface, so there always is a present family
+    let (graphics_family, present_family) =
+        pick_graphics_present_families(&families, true, prefer_split)?;
+    Ok((
+        graphics_family,
+        present_family.ok_or(VwError::QueueFamilyNotFound("presentation"))?,
+    ))
+}
+
+fn create_logical_device(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    graphics_family: u32,
+    present_family: u32,
+) -> anyhow::Result<ash::Device> {
+    let priorities = [1.0];
+    let mut queue_create_infos = vec![
+        vk::DeviceQueueCreateInfo::default()
+            .queue_family_index(graphics_family)
+            .queue_priorities(&priorities),
+    ];
+    if present_family != graphics_family {
+        queue_create_infos.push(
+            vk::DeviceQueueCreateInfo::default()
+                .queue_family_index(present_family)
+                .queue_priorities(&priorities),
+        );
+    }
+
+    let extension_names = REQUIRED_DEVICE_EXTENSIONS.map(CStr::as_ptr);
+
+    // Geometry shaders are optional, same as for the vulkano backend
+    let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+    let features = vk::PhysicalDeviceFeatures::default()
+        .geometry_shader(supported_features.geometry_shader == vk::TRUE);
+    let mut features11 = vk::PhysicalDeviceVulkan11Features::default().shader_draw_parameters(true);
+    let mut features13 = vk::PhysicalDeviceVulkan13Features::default().dynamic_rendering(true);
+
+    let device_create_info = vk::DeviceCreateInfo::default()
+        .queue_create_infos(&queue_create_infos)
+        .enabled_extension_names(&extension_names)
+        .enabled_features(&features)
+        .push_next(&mut features11)
+        .push_next(&mut features13);
+
+    let device = unsafe { instance.create_device(physical_device, &device_create_info, None) }?;
+
+    Ok(device)
+}
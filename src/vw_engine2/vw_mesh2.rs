@@ -0,0 +1,106 @@
+use ash::vk;
+
+use crate::{
+    vw_engine::{VwBufferUsage, VwVertex, VwVertexLayout},
+    vw_engine2::{
+        vw_buffer2::{VwBuffer2, VwStagingRing2},
+        vw_device2::VwDevice2,
+    },
+};
+
+/// Indexed triangle geometry in device-local buffers, the counterpart of the vulkano backend's
+/// `VwMesh`. Has to be destroyed explicitly before the device.
+pub struct VwMesh2 {
+    vertex_buffer: VwBuffer2,
+    index_buffer: VwBuffer2,
+    index_count: u32,
+}
+
+impl VwMesh2 {
+    /// Uploads `vertices` packed to `layout` and the `u32` `indices` through `staging`. The mesh
+    /// can be drawn once the ring has been flushed.
+    pub fn new(
+        device: &VwDevice2,
+        staging: &mut VwStagingRing2,
+        layout: &VwVertexLayout,
+        vertices: &[VwVertex],
+        indices: &[u32],
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            !vertices.is_empty() && !indices.is_empty(),
+            "Invalid mesh: the mesh is empty"
+        );
+        if let Some(index) = indices
+            .iter()
+            .find(|&&index| index as usize >= vertices.len())
+        {
+            anyhow::bail!(
+                "Invalid mesh: index {index} is out of range for {} vertices",
+                vertices.len()
+            );
+        }
+
+        let vertex_data = layout.pack(vertices);
+        let index_data = indices
+            .iter()
+            .flat_map(|index| index.to_ne_bytes())
+            .collect::<Vec<_>>();
+
+        let mut vertex_buffer = VwBuffer2::new(
+            device,
+            VwBufferUsage::Vertex,
+            vertex_data.len() as vk::DeviceSize,
+        )?;
+        let mut index_buffer = match VwBuffer2::new(
+            device,
+            VwBufferUsage::Index,
+            index_data.len() as vk::DeviceSize,
+        ) {
+            Ok(index_buffer) => index_buffer,
+            Err(err) => {
+                unsafe { vertex_buffer.destroy(device) };
+                return Err(err);
+            }
+        };
+
+        let uploaded = staging
+            .upload(device, &vertex_buffer, 0, &vertex_data)
+            .and_then(|()| staging.upload(device, &index_buffer, 0, &index_data));
+        if let Err(err) = uploaded {
+            unsafe {
+                vertex_buffer.destroy(device);
+                index_buffer.destroy(device);
+            }
+            return Err(err);
+        }
+
+        Ok(VwMesh2 {
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+        })
+    }
+
+    /// Binds the buffers and records an indexed draw. The bound pipeline must use the layout the
+    /// mesh was created with.
+    pub unsafe fn draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer.buffer()], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                self.index_buffer.buffer(),
+                0,
+                vk::IndexType::UINT32,
+            );
+            device.cmd_draw_indexed(command_buffer, self.index_count, 1, 0, 0, 0);
+        }
+    }
+
+    /// Destroys both buffers. The GPU must be done with them.
+    pub unsafe fn destroy(&mut self, device: &VwDevice2) {
+        unsafe {
+            self.vertex_buffer.destroy(device);
+            self.index_buffer.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::Cursor;
+
+use ash::vk;
+
+use crate::vw_engine::{VwPushConstants, VwVertexLayout};
+
+pub struct VwPipeline2 {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+}
+
+impl VwPipeline2 {
+    /// Builds a graphics pipeline for dynamic rendering into a single color attachment of
+    /// `color_format` and a depth attachment of `depth_format`, drawing meshes with
+    /// `vertex_layout`. Viewport and scissor are dynamic, so the pipeline survives resizes. Uses
+    /// the shader's unlit entry points, which take no descriptors.
+    pub fn new(
+        device: &ash::Device,
+        shader_code: &[u8],
+        color_format: vk::Format,
+        depth_format: vk::Format,
+        vertex_layout: &VwVertexLayout,
+    ) -> anyhow::Result<Self> {
+        let words = ash::util::read_spv(&mut Cursor::new(shader_code))?;
+        let shader_create_info = vk::ShaderModuleCreateInfo::default().code(&words);
+
+        // The safety of this block depends on the validity of the provided SPIR-V code.
+        let shader_module = unsafe { device.create_shader_module(&shader_create_info, None) }?;
+
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(shader_module)
+                .name(c"vertMainUnlit"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(shader_module)
+                .name(c"fragMainUnlit"),
+        ];
+
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(size_of::<VwPushConstants>() as u32)];
+        let layout_create_info =
+            vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&push_constant_ranges);
+        let layout = unsafe { device.create_pipeline_layout(&layout_create_info, None) }?;
+
+        let vertex_bindings = [vk::VertexInputBindingDescription::default()
+            .binding(0)
+            .stride(vertex_layout.stride())
+            .input_rate(vk::VertexInputRate::VERTEX)];
+        let vertex_attributes = vertex_layout
+            .offsets()
+            .map(|(attribute, offset)| {
+                vk::VertexInputAttributeDescription::default()
+                    .location(attribute.location())
+                    .binding(0)
+                    .format(attribute.format().into())
+                    .offset(offset)
+            })
+            .collect::<Vec<_>>();
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&vertex_bindings)
+            .vertex_attribute_descriptions(&vertex_attributes);
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .line_width(1.0);
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        // Nearer fragments win, the depth buffer is cleared to the far plane every frame
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(true)
+            .depth_write_enable(true)
+            .depth_compare_op(vk::CompareOp::LESS);
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::default()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)];
+        let color_blend_state =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&color_blend_attachments);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let color_attachment_formats = [color_format];
+        let mut rendering_info = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&color_attachment_formats)
+            .depth_attachment_format(depth_format);
+
+        let create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(layout)
+            .push_next(&mut rendering_info);
+
+        let pipelines = unsafe {
+            device.create_graphics_pipelines(vk::PipelineCache::null(), &[create_info], None)
+        };
+
+        // The module is baked into the pipeline and no longer needed either way
+        unsafe { device.destroy_shader_module(shader_module, None) };
+
+        let pipeline = match pipelines {
+            Ok(pipelines) => pipelines[0],
+            Err((_, err)) => {
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                return Err(err.into());
+            }
+        };
+
+        Ok(VwPipeline2 { pipeline, layout })
+    }
+
+    pub fn pipeline(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+
+    pub fn layout(&self) -> vk::PipelineLayout {
+        self.layout
+    }
+
+    /// Destroys the pipeline and its layout. The device must be idle.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use ash::vk;
+
+use crate::vw_engine2::{
+    create_semaphores, destroy_semaphores, vw_device2::VwDevice2, vw_swapchain2::VwSwapchain2,
+};
+
+/// Hands swapchain images from the graphics to the present queue family when the swapchain uses
+/// exclusive sharing across split families. The frame's command buffer records the release half,
+/// this records the acquire half once per image and submits it to the present queue in between
+/// rendering and presentation.
+pub struct VwPresentOwnership2 {
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+    /// Signaled by the acquire submission for each image, presentation waits on it.
+    acquired: Vec<vk::Semaphore>,
+}
+
+impl VwPresentOwnership2 {
+    pub fn new(device: &VwDevice2, swapchain: &VwSwapchain2) -> anyhow::Result<Self> {
+        let command_pool = unsafe {
+            device.device().create_command_pool(
+                &vk::CommandPoolCreateInfo::default().queue_family_index(device.present_family()),
+                None,
+            )
+        }?;
+
+        let mut present_ownership = VwPresentOwnership2 {
+            command_pool,
+            command_buffers: vec![],
+            acquired: vec![],
+        };
+        present_ownership.record(device, swapchain)?;
+
+        Ok(present_ownership)
+    }
+
+    /// Records the acquire barriers for the current swapchain images, replacing the previous
+    /// ones. The device must be idle.
+    pub fn record(&mut self, device: &VwDevice2, swapchain: &VwSwapchain2) -> anyhow::Result<()> {
+        let ash_device = device.device();
+        unsafe { self.destroy_per_image(ash_device) };
+
+        let allocate_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(swapchain.images().len() as u32);
+        self.command_buffers = unsafe { ash_device.allocate_command_buffers(&allocate_info) }?;
+        self.acquired = create_semaphores(ash_device, swapchain.images().len())?;
+
+        for (command_buffer, image) in self.command_buffers.iter().zip(swapchain.images()) {
+            // Has to match the release barrier exactly, including the layout transition
+            let barrier = vk::ImageMemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::empty())
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+                .src_queue_family_index(device.graphics_family())
+                .dst_queue_family_index(device.present_family())
+                .image(*image)
+                .subresource_range(
+                    vk::ImageSubresourceRange::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .level_count(1)
+                        .layer_count(1),
+                );
+
+            // The same image can come around again before the previous submission retired
+            unsafe {
+                ash_device.begin_command_buffer(
+                    *command_buffer,
+                    &vk::CommandBufferBeginInfo::default()
+                        .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE),
+                )?;
+                ash_device.cmd_pipeline_barrier(
+                    *command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                );
+                ash_device.end_command_buffer(*command_buffer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Submits the acquire barrier for `image_index` to the present queue once `rendered` is
+    /// signaled. Returns the semaphore presentation has to wait on instead.
+    pub fn submit(
+        &self,
+        device: &VwDevice2,
+        image_index: usize,
+        rendered: vk::Semaphore,
+    ) -> anyhow::Result<vk::Semaphore> {
+        let wait_semaphores = [rendered];
+        let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+        let command_buffers = [self.command_buffers[image_index]];
+        let signal_semaphores = [self.acquired[image_index]];
+        let submit_info = vk::SubmitInfo::default()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+
+        unsafe {
+            device
+                .device()
+                .queue_submit(device.present_queue(), &[submit_info], vk::Fence::null())
+        }?;
+
+        Ok(self.acquired[image_index])
+    }
+
+    /// Destroys the command pool and semaphores. The device must be idle.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            self.destroy_per_image(device);
+            device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+
+    unsafe fn destroy_per_image(&mut self, device: &ash::Device) {
+        unsafe {
+            if !self.command_buffers.is_empty() {
+                device.free_command_buffers(self.command_pool, &self.command_buffers);
+                self.command_buffers.clear();
+            }
+            destroy_semaphores(device, &mut self.acquired);
+        }
+    }
+}
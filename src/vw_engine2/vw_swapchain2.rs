@@ -0,0 +1,410 @@
+use ash::{khr, vk};
+
+use crate::{
+    vw_engine::{VwColorOutput, VwPreRotation, VwPresentPolicy, VwSwapchainSharing},
+    vw_engine2::vw_device2::VwDevice2,
+    vw_queue_family::{VwImageSharing, choose_image_sharing},
+};
+
+/// The ash counterpart of `VwSwapchainCreateInfo`.
+pub struct VwSwapchain2CreateInfo {
+    /// Mapped to the best present mode the surface supports, can be changed later with
+    /// `set_present_policy`.
+    pub present_policy: VwPresentPolicy,
+    /// Number of images to ask for, clamped to what the surface allows.
+    pub image_count: u32,
+    /// Only matters when the graphics and present queues come from different families.
+    pub sharing: VwSwapchainSharing,
+    /// Wide-gamut or HDR output to try for, falling back to SDR when the surface can't do it.
+    pub color_output: VwColorOutput,
+    /// Uses the display's current transform instead of letting the compositor rotate.
+    pub pre_rotation: bool,
+    /// Blends the window with what is behind it, with premultiplied alpha if the surface allows.
+    pub transparent: bool,
+}
+
+pub struct VwSwapchain2 {
+    loader: khr::swapchain::Device,
+    swapchain: vk::SwapchainKHR,
+    images: Vec<vk::Image>,
+    image_views: Vec<vk::ImageView>,
+    surface_format: vk::SurfaceFormatKHR,
+    color_output: VwColorOutput,
+    pre_rotation: bool,
+    transparent: bool,
+    pre_transform: vk::SurfaceTransformFlagsKHR,
+    composite_alpha: vk::CompositeAlphaFlagsKHR,
+    present_policy: VwPresentPolicy,
+    requested_image_count: u32,
+    present_mode: vk::PresentModeKHR,
+    sharing: VwSwapchainSharing,
+    extent: vk::Extent2D,
+}
+
+impl VwSwapchain2 {
+    /// Creates the swapchain. `drawable_extent` is the window's size in pixels and is used when
+    /// the surface leaves the extent up to the application.
+    pub fn new(
+        device: &VwDevice2,
+        drawable_extent: [u32; 2],
+        create_info: &VwSwapchain2CreateInfo,
+    ) -> anyhow::Result<Self> {
+        let loader = khr::swapchain::Device::new(device.instance(), device.device());
+        let swapchain_support = device.swap_chain_support()?;
+
+        let (color_output, surface_format) =
+            choose_surface_format(&swapchain_support.surface_formats, create_info.color_output);
+
+        let mut vw_swapchain = VwSwapchain2 {
+            loader,
+            swapchain: vk::SwapchainKHR::null(),
+            images: vec![],
+            image_views: vec![],
+            surface_format,
+            color_output,
+            pre_rotation: create_info.pre_rotation,
+            transparent: create_info.transparent,
+            pre_transform: vk::SurfaceTransformFlagsKHR::IDENTITY,
+            composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+            present_policy: create_info.present_policy,
+            requested_image_count: create_info.image_count,
+            present_mode: vk::PresentModeKHR::FIFO,
+            sharing: create_info.sharing,
+            extent: vk::Extent2D::default(),
+        };
+        vw_swapchain.recreate(device, drawable_extent)?;
+
+        Ok(vw_swapchain)
+    }
+
+    /// Rebuilds the swapchain for the current surface size, passing the existing swapchain as
+    /// `old_swapchain`. Returns `false` without recreating anything while the surface has a
+    /// zero-sized extent, e.g. when minimized. The device must be idle.
+    pub fn recreate(
+        &mut self,
+        device: &VwDevice2,
+        drawable_extent: [u32; 2],
+    ) -> anyhow::Result<bool> {
+        let swapchain_support = device.swap_chain_support()?;
+        let capabilities = &swapchain_support.surface_capabilities;
+        // The display may have been rotated since the swapchain was created
+        let pre_transform = choose_pre_transform(capabilities, self.pre_rotation);
+        let extent = choose_extent(capabilities, drawable_extent, pre_transform);
+
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(false);
+        }
+
+        self.present_mode =
+            choose_present_mode(&swapchain_support.present_modes, self.present_policy);
+        self.pre_transform = pre_transform;
+        self.composite_alpha = choose_composite_alpha(capabilities, self.transparent);
+
+        // A max_image_count of 0 means there is no limit
+        let mut image_count = self.requested_image_count.max(capabilities.min_image_count);
+        if capabilities.max_image_count > 0 {
+            image_count = image_count.min(capabilities.max_image_count);
+        }
+
+        let mut create_info = vk::SwapchainCreateInfoKHR::default()
+            .surface(device.surface())
+            .min_image_count(image_count)
+            .image_format(self.surface_format.format)
+            .image_color_space(self.surface_format.color_space)
+            .image_extent(extent)
+            .image_array_layers(1)
+            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .pre_transform(self.pre_transform)
+            .composite_alpha(self.composite_alpha)
+            .present_mode(self.present_mode)
+            .clipped(true)
+            .old_swapchain(self.swapchain);
+
+        // With split families the images are either shared, or handed over with ownership
+        // transfers by the engine, see `needs_ownership_transfer`
+        let image_sharing = self.image_sharing(device);
+        if let VwImageSharing::Concurrent(queue_family_indices) = &image_sharing {
+            create_info = create_info
+                .image_sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(queue_family_indices);
+        }
+
+        let swapchain = unsafe { self.loader.create_swapchain(&create_info, None) }?;
+
+        unsafe { self.destroy(device) };
+
+        self.images = unsafe { self.loader.get_swapchain_images(swapchain) }?;
+        self.image_views =
+            create_image_views(device.device(), self.surface_format.format, &self.images)?;
+        self.swapchain = swapchain;
+        self.extent = extent;
+
+        Ok(true)
+    }
+
+    /// Whether images have to be released by the graphics family and acquired by the present
+    /// family before they can be presented.
+    pub fn needs_ownership_transfer(&self, device: &VwDevice2) -> bool {
+        matches!(
+            self.image_sharing(device),
+            VwImageSharing::ExclusiveTransfer { .. }
+        )
+    }
+
+    fn image_sharing(&self, device: &VwDevice2) -> VwImageSharing {
+        choose_image_sharing(
+            device.graphics_family(),
+            device.present_family(),
+            self.sharing,
+        )
+    }
+
+    /// Takes effect the next time the swapchain is recreated.
+    pub fn set_present_policy(&mut self, present_policy: VwPresentPolicy) {
+        self.present_policy = present_policy;
+    }
+
+    pub fn present_policy(&self) -> VwPresentPolicy {
+        self.present_policy
+    }
+
+    /// The number of images the implementation actually created, which can be more than asked
+    /// for.
+    pub fn image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn loader(&self) -> &khr::swapchain::Device {
+        &self.loader
+    }
+
+    pub fn swapchain(&self) -> vk::SwapchainKHR {
+        self.swapchain
+    }
+
+    pub fn images(&self) -> &[vk::Image] {
+        &self.images
+    }
+
+    pub fn image_views(&self) -> &[vk::ImageView] {
+        &self.image_views
+    }
+
+    pub fn surface_format(&self) -> vk::Format {
+        self.surface_format.format
+    }
+
+    /// The output that was actually chosen, `Sdr` when the requested one wasn't available.
+    pub fn color_output(&self) -> VwColorOutput {
+        self.color_output
+    }
+
+    pub fn color_space(&self) -> vk::ColorSpaceKHR {
+        self.surface_format.color_space
+    }
+
+    /// The rotation projection matrices have to apply, `Identity` unless pre-rotation was asked
+    /// for and the display is rotated.
+    pub fn pre_rotation(&self) -> VwPreRotation {
+        pre_rotation_of(self.pre_transform).unwrap_or_default()
+    }
+
+    /// Whether the window is blended with what is behind it, so the clear color needs a zero
+    /// alpha.
+    pub fn is_transparent(&self) -> bool {
+        self.composite_alpha == vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED
+            || self.composite_alpha == vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Destroys the image views and the swapchain. The device must be idle.
+    pub unsafe fn destroy(&mut self, device: &VwDevice2) {
+        unsafe {
+            for image_view in self.image_views.drain(..) {
+                device.device().destroy_image_view(image_view, None);
+            }
+            if self.swapchain != vk::SwapchainKHR::null() {
+                self.loader.destroy_swapchain(self.swapchain, None);
+                self.swapchain = vk::SwapchainKHR::null();
+            }
+        }
+    }
+}
+
+fn choose_surface_format(
+    available_formats: &[vk::SurfaceFormatKHR],
+    requested: VwColorOutput,
+) -> (VwColorOutput, vk::SurfaceFormatKHR) {
+    let find = |output: VwColorOutput| {
+        output
+            .preferred_surface_formats()
+            .iter()
+            .map(|&(format, color_space)| vk::SurfaceFormatKHR {
+                format: format.into(),
+                color_space: color_space.into(),
+            })
+            .find(|surface_format| {
+                available_formats.iter().any(|available| {
+                    available.format == surface_format.format
+                        && available.color_space == surface_format.color_space
+                })
+            })
+            .map(|surface_format| (output, surface_format))
+    };
+
+    if let Some(chosen) = find(requested) {
+        log::info!(
+            "{requested:?} output uses {:?} in {:?}",
+            chosen.1.format,
+            chosen.1.color_space
+        );
+        return chosen;
+    }
+    if requested != VwColorOutput::Sdr {
+        log::warn!("The surface doesn't support {requested:?} output, falling back to SDR");
+    }
+
+    find(VwColorOutput::Sdr).unwrap_or((VwColorOutput::Sdr, available_formats[0]))
+}
+
+fn pre_rotation_of(transform: vk::SurfaceTransformFlagsKHR) -> Option<VwPreRotation> {
+    match transform {
+        vk::SurfaceTransformFlagsKHR::IDENTITY => Some(VwPreRotation::Identity),
+        vk::SurfaceTransformFlagsKHR::ROTATE_90 => Some(VwPreRotation::Rotate90),
+        vk::SurfaceTransformFlagsKHR::ROTATE_180 => Some(VwPreRotation::Rotate180),
+        vk::SurfaceTransformFlagsKHR::ROTATE_270 => Some(VwPreRotation::Rotate270),
+        _ => None,
+    }
+}
+
+fn choose_pre_transform(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    pre_rotation: bool,
+) -> vk::SurfaceTransformFlagsKHR {
+    let current_transform = capabilities.current_transform;
+
+    if pre_rotation && pre_rotation_of(current_transform).is_some() {
+        current_transform
+    } else if capabilities
+        .supported_transforms
+        .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
+    {
+        vk::SurfaceTransformFlagsKHR::IDENTITY
+    } else {
+        log::warn!(
+            "The surface doesn't support the identity transform, using {current_transform:?}"
+        );
+        current_transform
+    }
+}
+
+fn choose_composite_alpha(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    transparent: bool,
+) -> vk::CompositeAlphaFlagsKHR {
+    use vk::CompositeAlphaFlagsKHR as Alpha;
+
+    let preferred = if transparent {
+        [
+            Alpha::PRE_MULTIPLIED,
+            Alpha::POST_MULTIPLIED,
+            Alpha::INHERIT,
+            Alpha::OPAQUE,
+        ]
+    } else {
+        [
+            Alpha::OPAQUE,
+            Alpha::INHERIT,
+            Alpha::PRE_MULTIPLIED,
+            Alpha::POST_MULTIPLIED,
+        ]
+    };
+
+    let composite_alpha = preferred
+        .into_iter()
+        .find(|&composite_alpha| {
+            capabilities
+                .supported_composite_alpha
+                .contains(composite_alpha)
+        })
+        .unwrap_or(Alpha::OPAQUE);
+
+    if transparent
+        && composite_alpha != Alpha::PRE_MULTIPLIED
+        && composite_alpha != Alpha::POST_MULTIPLIED
+    {
+        log::warn!("The surface can't blend with the desktop, using {composite_alpha:?} alpha");
+    }
+    composite_alpha
+}
+
+fn choose_present_mode(
+    present_modes: &[vk::PresentModeKHR],
+    policy: VwPresentPolicy,
+) -> vk::PresentModeKHR {
+    let present_mode = policy
+        .preferred_present_modes()
+        .iter()
+        .map(|&present_mode| vk::PresentModeKHR::from(present_mode))
+        .find(|present_mode| present_modes.contains(present_mode))
+        .unwrap_or(vk::PresentModeKHR::FIFO);
+
+    log::info!("Present policy {policy:?} uses {present_mode:?}");
+    present_mode
+}
+
+/// The surface's extent, or the drawable size when the surface leaves it to the swapchain. The
+/// drawable size is in the window's orientation, so it is swapped when `pre_transform` rotates
+/// the images by a quarter turn.
+fn choose_extent(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    drawable_extent: [u32; 2],
+    pre_transform: vk::SurfaceTransformFlagsKHR,
+) -> vk::Extent2D {
+    // u32::MAX means the surface size is determined by the swapchain
+    if capabilities.current_extent.width != u32::MAX {
+        return capabilities.current_extent;
+    }
+
+    let [mut width, mut height] = drawable_extent;
+    if pre_rotation_of(pre_transform).is_some_and(VwPreRotation::swaps_extent) {
+        (width, height) = (height, width);
+    }
+    let min = capabilities.min_image_extent;
+    let max = capabilities.max_image_extent;
+
+    vk::Extent2D {
+        width: width.clamp(min.width, max.width),
+        height: height.clamp(min.height, max.height),
+    }
+}
+
+fn create_image_views(
+    device: &ash::Device,
+    format: vk::Format,
+    images: &[vk::Image],
+) -> anyhow::Result<Vec<vk::ImageView>> {
+    images
+        .iter()
+        .map(|image| {
+            let create_info = vk::ImageViewCreateInfo::default()
+                .image(*image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format)
+                .components(vk::ComponentMapping::default())
+                .subresource_range(
+                    vk::ImageSubresourceRange::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(1),
+                );
+            Ok(unsafe { device.create_image_view(&create_info, None) }?)
+        })
+        .collect()
+}
@@ -0,0 +1,92 @@
+use thiserror::Error;
+use vulkano::{
+    LoadingError, Validated, ValidationError, VulkanError, buffer::AllocateBufferError,
+    command_buffer::CommandBufferExecError, image::AllocateImageError,
+    pipeline::layout::IntoPipelineLayoutCreateInfoError, shader::spirv::SpirvBytesNotMultipleOf4,
+    sync::HostAccessError,
+};
+
+/// Errors the engine can fail with, so applications embedding it can react instead of panicking.
+#[derive(Debug, Error)]
+pub enum VwError {
+    #[error("SDL2 error: {0}")]
+    Sdl(String),
+
+    #[error("Required extension {0} is not supported")]
+    MissingExtension(String),
+
+    #[error("No suitable Vulkan physical device found, run with `info` to see why")]
+    NoSuitableDevice,
+
+    #[error("Couldn't find queue family that supports {0}")]
+    QueueFamilyNotFound(&'static str),
+
+    #[error("Failed to create Vulkan surface: {0}")]
+    SurfaceCreation(String),
+
+    #[error("Couldn't find shader entry point {0}")]
+    MissingEntryPoint(&'static str),
+
+    #[error("Invalid mesh: {0}")]
+    InvalidMesh(String),
+
+    #[error("Failed to load glTF file: {0}")]
+    Gltf(#[from] gltf::Error),
+
+    #[error("Failed to load OBJ file: {0}")]
+    Obj(#[from] tobj::LoadError),
+
+    #[error("Failed to decode image: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("Invalid texture: {0}")]
+    InvalidTexture(String),
+
+    #[error("Invalid material: {0}")]
+    InvalidMaterial(String),
+
+    #[error("The device is headless and has no surface for a swapchain")]
+    HeadlessSwapchain,
+
+    #[error("Failed to create swapchain: {0}")]
+    SwapchainCreation(#[source] Validated<VulkanError>),
+
+    #[error("Shader code is not valid SPIR-V: {0}")]
+    InvalidSpirv(#[from] SpirvBytesNotMultipleOf4),
+
+    #[error(transparent)]
+    PipelineLayout(#[from] IntoPipelineLayoutCreateInfoError),
+
+    #[error(transparent)]
+    ImageAllocation(#[from] AllocateImageError),
+
+    #[error(transparent)]
+    BufferAllocation(#[from] AllocateBufferError),
+
+    #[error("Failed to execute command buffer: {0}")]
+    CommandBufferExec(#[from] CommandBufferExecError),
+
+    #[error("Buffer memory is not accessible from the host: {0}")]
+    HostAccess(#[from] HostAccessError),
+
+    #[error(transparent)]
+    Loading(#[from] LoadingError),
+
+    #[error(transparent)]
+    Validation(#[from] Box<ValidationError>),
+
+    #[error(transparent)]
+    Vulkan(#[from] VulkanError),
+}
+
+impl<E> From<Validated<E>> for VwError
+where
+    VwError: From<E>,
+{
+    fn from(err: Validated<E>) -> Self {
+        match err {
+            Validated::Error(err) => err.into(),
+            Validated::ValidationError(err) => VwError::Validation(err),
+        }
+    }
+}
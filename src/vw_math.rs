@@ -0,0 +1,212 @@
+/// A 4x4 matrix stored column by column, the layout glTF and the shaders use.
+pub type VwMat4 = [[f32; 4]; 4];
+
+pub const IDENTITY: VwMat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// `a * b`, so `b` is applied first.
+pub fn mul(a: &VwMat4, b: &VwMat4) -> VwMat4 {
+    let mut out = [[0.0; 4]; 4];
+    for (column, out_column) in out.iter_mut().enumerate() {
+        for (row, value) in out_column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[column][k]).sum();
+        }
+    }
+    out
+}
+
+/// Transforms a point by an affine matrix.
+pub fn transform_point(m: &VwMat4, p: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|row| m[0][row] * p[0] + m[1][row] * p[1] + m[2][row] * p[2] + m[3][row])
+}
+
+/// Transforms a direction, ignoring translation.
+pub fn transform_vector(m: &VwMat4, v: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|row| m[0][row] * v[0] + m[1][row] * v[1] + m[2][row] * v[2])
+}
+
+/// Transforms a normal with the cofactor matrix, which is the inverse transpose up to scale, so
+/// non-uniform scaling keeps normals perpendicular to their surface.
+pub fn transform_normal(m: &VwMat4, n: [f32; 3]) -> [f32; 3] {
+    let [c0, c1, c2] = [m[0], m[1], m[2]].map(|column| [column[0], column[1], column[2]]);
+    let cofactors = [cross(c1, c2), cross(c2, c0), cross(c0, c1)];
+    // Mirroring transforms flip the winding, so the normal has to flip with it
+    let sign = if dot(c0, cofactors[0]) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+
+    let normal = [0, 1, 2].map(|row| {
+        sign * (cofactors[0][row] * n[0] + cofactors[1][row] * n[1] + cofactors[2][row] * n[2])
+    });
+    normalize(normal)
+}
+
+/// Inverts a transform made of rotation, translation and uniform scale, which is what camera
+/// nodes use. Scale is dropped.
+pub fn rigid_inverse(m: &VwMat4) -> VwMat4 {
+    let axes = [m[0], m[1], m[2]].map(|column| normalize([column[0], column[1], column[2]]));
+    let translation = [m[3][0], m[3][1], m[3][2]];
+
+    let mut out = IDENTITY;
+    for (column, axis) in axes.iter().enumerate() {
+        for (row, component) in axis.iter().enumerate() {
+            out[row][column] = *component;
+        }
+        out[3][column] = -dot(*axis, translation);
+    }
+    out
+}
+
+/// A right-handed view matrix looking from `eye` at `target`.
+pub fn look_at(eye: [f32; 3], target: [f32; 3], up: [f32; 3]) -> VwMat4 {
+    let forward = normalize(sub(target, eye));
+    let right = normalize(cross(forward, up));
+    let up = cross(right, forward);
+
+    [
+        [right[0], up[0], -forward[0], 0.0],
+        [right[1], up[1], -forward[1], 0.0],
+        [right[2], up[2], -forward[2], 0.0],
+        [-dot(right, eye), -dot(up, eye), dot(forward, eye), 1.0],
+    ]
+}
+
+/// A perspective projection for a right-handed view space looking down -Z, into Vulkan's clip
+/// space with Y down and depth from 0 to 1. Without `zfar` the far plane is at infinity.
+pub fn perspective(yfov: f32, aspect_ratio: f32, znear: f32, zfar: Option<f32>) -> VwMat4 {
+    let focal_length = 1.0 / (yfov / 2.0).tan();
+    let (depth_scale, depth_offset) = match zfar {
+        Some(zfar) => (zfar / (znear - zfar), znear * zfar / (znear - zfar)),
+        None => (-1.0, -znear),
+    };
+
+    [
+        [focal_length / aspect_ratio, 0.0, 0.0, 0.0],
+        [0.0, -focal_length, 0.0, 0.0],
+        [0.0, 0.0, depth_scale, -1.0],
+        [0.0, 0.0, depth_offset, 0.0],
+    ]
+}
+
+/// An orthographic projection with half extents `xmag` and `ymag`, into Vulkan's clip space.
+pub fn orthographic(xmag: f32, ymag: f32, znear: f32, zfar: f32) -> VwMat4 {
+    [
+        [1.0 / xmag, 0.0, 0.0, 0.0],
+        [0.0, -1.0 / ymag, 0.0, 0.0],
+        [0.0, 0.0, 1.0 / (znear - zfar), 0.0],
+        [0.0, 0.0, znear / (znear - zfar), 1.0],
+    ]
+}
+
+/// Rotates clip space around Z, to be applied after the projection when the swapchain is
+/// pre-rotated by `degrees`.
+pub fn rotate_z(degrees: u32) -> VwMat4 {
+    let (sin, cos) = (degrees as f32).to_radians().sin_cos();
+
+    [
+        [cos, sin, 0.0, 0.0],
+        [-sin, cos, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]
+}
+
+/// Recovers the camera from a view-projection matrix, as the world-space point that projects to
+/// the center of clip space with W = 0. Returns the position with W = 1, or for an orthographic
+/// projection the direction towards the camera with W = 0.
+pub fn eye(view_projection: &VwMat4) -> [f32; 4] {
+    // Clip X, Y and W vanish at the eye, so it spans the null space of those three rows
+    let rows = [0, 1, 3].map(|row| view_projection.map(|column| column[row]));
+    let minor = |skip: usize| {
+        let [a, b, c] = rows.map(|row| {
+            let mut kept = [0.0; 3];
+            for (out, column) in kept.iter_mut().zip((0..4).filter(|&column| column != skip)) {
+                *out = row[column];
+            }
+            kept
+        });
+        dot(a, cross(b, c))
+    };
+    let null = [minor(0), -minor(1), minor(2), -minor(3)];
+
+    if null[3].abs() > f32::EPSILON * null.iter().map(|value| value.abs()).sum::<f32>() {
+        [null[0] / null[3], null[1] / null[3], null[2] / null[3], 1.0]
+    } else {
+        // Depth grows away from the camera, so the direction towards it has to decrease it
+        let direction = normalize([null[0], null[1], null[2]]);
+        let depth_row = [0, 1, 2].map(|column| view_projection[column][2]);
+        let sign = if dot(depth_row, direction) > 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        [
+            sign * direction[0],
+            sign * direction[1],
+            sign * direction[2],
+            0.0,
+        ]
+    }
+}
+
+pub fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// Returns zero vectors unchanged instead of dividing by zero.
+pub fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = dot(v, v).sqrt();
+    if length > 0.0 {
+        v.map(|component| component / length)
+    } else {
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: [f32; 4], expected: [f32; 4]) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 1e-4,
+                "{actual:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn eye_of_perspective_camera() {
+        let view = look_at([1.0, 2.0, 3.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        for zfar in [Some(100.0), None] {
+            let view_projection = mul(&perspective(1.0, 1.5, 0.1, zfar), &view);
+            assert_near(eye(&view_projection), [1.0, 2.0, 3.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn eye_of_orthographic_camera() {
+        let view = look_at([0.0, 0.0, 5.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let view_projection = mul(&orthographic(2.0, 2.0, 0.1, 100.0), &view);
+        assert_near(eye(&view_projection), [0.0, 0.0, 1.0, 0.0]);
+    }
+}
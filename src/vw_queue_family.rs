@@ -0,0 +1,200 @@
+use crate::{vw_engine::VwSwapchainSharing, vw_error::VwError};
+
+/// What picking queue families needs to know about one. Both backends gather this from their
+/// queue family properties and surface support, so the decisions below are made once.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VwQueueFamilySupport {
+    pub graphics: bool,
+    /// Whether the family can present to the surface, always `false` without one.
+    pub present: bool,
+}
+
+/// Picks the graphics family and, when `presentable`, the present family. A family doing both
+/// is preferred, unless `prefer_split` asks for a present family other than the graphics one to
+/// exercise the split-family paths on hardware where one family does both.
+pub fn pick_graphics_present_families(
+    families: &[VwQueueFamilySupport],
+    presentable: bool,
+    prefer_split: bool,
+) -> Result<(u32, Option<u32>), VwError> {
+    let graphics_index = families
+        .iter()
+        .position(|family| family.graphics)
+        .ok_or(VwError::QueueFamilyNotFound("graphics"))? as u32;
+
+    // Headless devices never present, so there is no present family to look for
+    if !presentable {
+        return Ok((graphics_index, None));
+    }
+
+    let mut present_index = None;
+    for (index, family) in families.iter().enumerate() {
+        let index = index as u32;
+        if !family.present {
+            continue;
+        }
+
+        if prefer_split {
+            if index != graphics_index {
+                log::warn!("Simulating split queue families, presenting from family {index}");
+                return Ok((graphics_index, Some(index)));
+            }
+        } else if family.graphics {
+            return Ok((index, Some(index)));
+        }
+
+        present_index.get_or_insert(index);
+    }
+
+    let present_index = present_index.ok_or(VwError::QueueFamilyNotFound("presentation"))?;
+    if prefer_split {
+        log::warn!(
+            "Only family {present_index} can present, split queue families can't be simulated"
+        );
+    }
+
+    Ok((graphics_index, Some(present_index)))
+}
+
+/// How swapchain images are shared between the graphics and present families.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VwImageSharing {
+    /// One family does both, so the images are exclusive and never change hands.
+    Exclusive,
+    /// The images are usable from both families at once.
+    Concurrent([u32; 2]),
+    /// The images belong to one family at a time. The graphics family releases each image after
+    /// rendering and the present family acquires it before presenting.
+    ExclusiveTransfer {
+        graphics_family: u32,
+        present_family: u32,
+    },
+}
+
+/// Decides the sharing by queue family, two queues of the same family never need any.
+pub fn choose_image_sharing(
+    graphics_family: u32,
+    present_family: u32,
+    sharing: VwSwapchainSharing,
+) -> VwImageSharing {
+    if graphics_family == present_family {
+        return VwImageSharing::Exclusive;
+    }
+
+    match sharing {
+        VwSwapchainSharing::Concurrent => {
+            VwImageSharing::Concurrent([graphics_family, present_family])
+        }
+        VwSwapchainSharing::Exclusive => VwImageSharing::ExclusiveTransfer {
+            graphics_family,
+            present_family,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAPHICS: VwQueueFamilySupport = VwQueueFamilySupport {
+        graphics: true,
+        present: false,
+    };
+    const PRESENT: VwQueueFamilySupport = VwQueueFamilySupport {
+        graphics: false,
+        present: true,
+    };
+    const GRAPHICS_PRESENT: VwQueueFamilySupport = VwQueueFamilySupport {
+        graphics: true,
+        present: true,
+    };
+    const TRANSFER: VwQueueFamilySupport = VwQueueFamilySupport {
+        graphics: false,
+        present: false,
+    };
+
+    #[test]
+    fn same_family() {
+        let families = [TRANSFER, GRAPHICS_PRESENT];
+        let (graphics, present) = pick_graphics_present_families(&families, true, false).unwrap();
+        assert_eq!((graphics, present), (1, Some(1)));
+
+        for sharing in [
+            VwSwapchainSharing::Concurrent,
+            VwSwapchainSharing::Exclusive,
+        ] {
+            assert_eq!(
+                choose_image_sharing(graphics, present.unwrap(), sharing),
+                VwImageSharing::Exclusive
+            );
+        }
+    }
+
+    #[test]
+    fn same_family_preferred_over_split() {
+        let families = [GRAPHICS, PRESENT, GRAPHICS_PRESENT];
+        assert_eq!(
+            pick_graphics_present_families(&families, true, false).unwrap(),
+            (2, Some(2))
+        );
+    }
+
+    #[test]
+    fn split_family() {
+        let families = [GRAPHICS, PRESENT];
+        let (graphics, present) = pick_graphics_present_families(&families, true, false).unwrap();
+        assert_eq!((graphics, present), (0, Some(1)));
+
+        assert_eq!(
+            choose_image_sharing(graphics, present.unwrap(), VwSwapchainSharing::Concurrent),
+            VwImageSharing::Concurrent([0, 1])
+        );
+        assert_eq!(
+            choose_image_sharing(graphics, present.unwrap(), VwSwapchainSharing::Exclusive),
+            VwImageSharing::ExclusiveTransfer {
+                graphics_family: 0,
+                present_family: 1
+            }
+        );
+    }
+
+    #[test]
+    fn simulated_split_family() {
+        let families = [GRAPHICS_PRESENT, PRESENT];
+        assert_eq!(
+            pick_graphics_present_families(&families, true, true).unwrap(),
+            (0, Some(1))
+        );
+
+        // Nothing to split when the only present family is the graphics one
+        let families = [GRAPHICS_PRESENT, TRANSFER];
+        assert_eq!(
+            pick_graphics_present_families(&families, true, true).unwrap(),
+            (0, Some(0))
+        );
+    }
+
+    #[test]
+    fn no_present_family() {
+        let families = [GRAPHICS, TRANSFER];
+        assert!(matches!(
+            pick_graphics_present_families(&families, true, false),
+            Err(VwError::QueueFamilyNotFound("presentation"))
+        ));
+
+        // Headless devices don't need one
+        assert_eq!(
+            pick_graphics_present_families(&families, false, false).unwrap(),
+            (0, None)
+        );
+    }
+
+    #[test]
+    fn no_graphics_family() {
+        let families = [PRESENT, TRANSFER];
+        assert!(matches!(
+            pick_graphics_present_families(&families, true, false),
+            Err(VwError::QueueFamilyNotFound("graphics"))
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+use sdl::{
+    event::{Event, WindowEvent},
+    keyboard::Scancode,
+};
+
+use crate::{
+    vw_engine::{
+        VkWizardEngine, VkWizardEngineCreateInfo, VwColorOutput, VwHeapStats, VwPreRotation,
+        VwPresentPolicy, VwTextureCreateInfo, VwTextureData, VwVertex, log_heap_stats,
+    },
+    vw_engine2::VwEngine2,
+    vw_error::VwError,
+    vw_math::VwMat4,
+    vw_scene::VwMaterial,
+    vw_window::VwWindow,
+};
+
+pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Which Vulkan binding the renderer is built on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VwBackend {
+    /// `VkWizardEngine`, built on vulkano.
+    #[default]
+    Vulkano,
+    /// `VwEngine2`, built directly on ash. Windowed rendering only.
+    Ash,
+}
+
+/// The part of an engine that application code talks to. Both backends implement it, so the
+/// game loop is written once and the backend is picked at startup.
+pub trait VwRenderer {
+    /// The window being rendered to, or `None` when rendering headless.
+    fn window(&self) -> Option<&VwWindow>;
+
+    /// Records and submits one frame, and presents it when there is a swapchain.
+    fn draw_frame(&mut self) -> anyhow::Result<()>;
+
+    /// Uploads a texture for materials to sample and returns its index, counting from the last
+    /// [`clear_scene`](Self::clear_scene). Blocks until the upload has finished.
+    fn add_texture(
+        &mut self,
+        data: &VwTextureData,
+        create_info: &VwTextureCreateInfo,
+    ) -> anyhow::Result<usize>;
+
+    /// Creates a material and returns its index, counting from the last
+    /// [`clear_scene`](Self::clear_scene). Its texture references are indices returned by
+    /// [`add_texture`](Self::add_texture).
+    fn add_material(&mut self, material: &VwMaterial) -> anyhow::Result<usize>;
+
+    /// Uploads an indexed triangle mesh and draws it every frame from then on with `material`, an
+    /// index returned by [`add_material`](Self::add_material), or the glTF default material for
+    /// `None`. Blocks until the upload has finished.
+    fn add_mesh(
+        &mut self,
+        vertices: &[VwVertex],
+        indices: &[u32],
+        material: Option<usize>,
+    ) -> anyhow::Result<()>;
+
+    /// Removes every mesh, material and texture, including the built-in fullscreen triangle.
+    fn clear_scene(&mut self) -> anyhow::Result<()>;
+
+    /// Sets the matrix every mesh is transformed by, from world space to clip space. Takes
+    /// effect from the next frame, the default is the identity. The renderer applies the
+    /// [`pre_rotation`](Self::pre_rotation) on top.
+    fn set_view_projection(&mut self, view_projection: VwMat4);
+
+    /// Marks the swapchain as stale, it is rebuilt before the next frame is drawn.
+    fn request_swapchain_recreate(&mut self);
+
+    /// The current present policy, `None` when there is no swapchain.
+    fn present_policy(&self) -> Option<VwPresentPolicy>;
+
+    /// Switches the present policy, recreating the swapchain before the next frame. Does
+    /// nothing without a swapchain.
+    fn set_present_policy(&mut self, present_policy: VwPresentPolicy);
+
+    /// The color output the swapchain ended up with, which the shaders encode for. `None` when
+    /// there is no swapchain.
+    fn color_output(&self) -> Option<VwColorOutput>;
+
+    /// The rotation the renderer applies to the view-projection matrix to match the display,
+    /// `Identity` without a swapchain or when pre-rotation is off. Updated whenever the
+    /// swapchain is recreated.
+    fn pre_rotation(&self) -> VwPreRotation;
+
+    /// Allocation counters per memory heap, for the buffers and images the engine allocated
+    /// itself.
+    fn heap_stats(&self) -> Vec<VwHeapStats>;
+
+    /// Blocks until all submitted frames have finished on the GPU.
+    fn wait_idle(&mut self) -> anyhow::Result<()>;
+
+    /// Runs the event loop until the window is closed. Without a window there are no events to
+    /// wait for, so a single frame is rendered. The memory usage is logged at the end.
+    fn run(&mut self) -> anyhow::Result<()> {
+        let Some(window) = self.window() else {
+            self.draw_frame()?;
+            self.wait_idle()?;
+            log_heap_stats(&self.heap_stats());
+            return Ok(());
+        };
+
+        let mut event_pump = window.event_pump()?;
+        'running: loop {
+            for event in event_pump.poll_iter() {
+                if !handle_event(self, event) {
+                    break 'running;
+                }
+            }
+
+            // Nothing can be presented while minimized, so block until the window comes back.
+            // The event that wakes us up still goes through the same handling, a restore or a
+            // resize has to mark the swapchain as stale
+            if self.window().is_some_and(VwWindow::is_minimized) {
+                if !handle_event(self, event_pump.wait_event()) {
+                    break 'running;
+                }
+                continue;
+            }
+
+            self.draw_frame()?;
+        }
+
+        self.wait_idle()?;
+        log_heap_stats(&self.heap_stats());
+        Ok(())
+    }
+}
+
+/// Reacts to one window event. Returns `false` when the application should quit.
+fn handle_event<R: VwRenderer + ?Sized>(renderer: &mut R, event: Event) -> bool {
+    match event {
+        Event::Quit { .. } => return false,
+        Event::KeyDown {
+            scancode: Some(Scancode::Escape),
+            ..
+        } => return false,
+        // Cycles through the present policies, e.g. to toggle vsync
+        Event::KeyDown {
+            scancode: Some(Scancode::V),
+            repeat: false,
+            ..
+        } => {
+            if let Some(present_policy) = renderer.present_policy() {
+                renderer.set_present_policy(present_policy.next());
+            }
+        }
+        Event::Window {
+            win_event: WindowEvent::SizeChanged(..) | WindowEvent::Restored,
+            ..
+        } => renderer.request_swapchain_recreate(),
+        _ => {}
+    }
+
+    true
+}
+
+/// Creates the renderer for `backend`.
+pub fn create_renderer(
+    backend: VwBackend,
+    create_info: VkWizardEngineCreateInfo,
+) -> anyhow::Result<Box<dyn VwRenderer>> {
+    log::info!("Using the {backend:?} backend");
+
+    Ok(match backend {
+        VwBackend::Vulkano => Box::new(VkWizardEngine::new(create_info)?),
+        VwBackend::Ash => Box::new(VwEngine2::new(create_info)?),
+    })
+}
+
+/// Checks that every extension in `required` appears in `supported`, returning the first one
+/// that doesn't. Shared by both backends, which only differ in how they list extensions.
+pub fn check_extension_support<'a>(
+    supported: impl IntoIterator<Item = &'a str>,
+    required: &[&str],
+) -> Result<(), VwError> {
+    let supported = supported.into_iter().collect::<Vec<_>>();
+
+    match required.iter().find(|name| !supported.contains(*name)) {
+        Some(missing) => {
+            log::info!("Supported extensions:");
+            for name in supported {
+                log::info!("\t{name}");
+            }
+            Err(VwError::MissingExtension(missing.to_string()))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Checks whether `layer` is among the installed `layers`. A missing layer is only a warning, so
+/// machines without the Vulkan SDK still start, just without validation.
+pub fn is_layer_available<'a>(layers: impl IntoIterator<Item = &'a str>, layer: &str) -> bool {
+    let available = layers.into_iter().any(|name| name == layer);
+
+    if !available {
+        log::warn!("{layer} is not installed, running without validation");
+    }
+
+    available
+}
@@ -0,0 +1,519 @@
+use std::collections::HashMap;
+
+use crate::{
+    vw_engine::{VwSamplerCreateInfo, VwTextureCreateInfo, VwTextureData, VwVertex},
+    vw_error::VwError,
+    vw_math::{self, VwMat4},
+    vw_renderer::VwRenderer,
+};
+
+mod vw_gltf;
+mod vw_obj;
+
+pub use vw_obj::VwGeneratedNormals;
+
+/// A scene loaded from a model file, kept on the CPU until it is handed to a
+/// [`VwRenderer`](crate::vw_renderer::VwRenderer). Meshes, materials, textures, images, cameras
+/// and nodes reference each other by index into the vectors here, like they do in glTF.
+#[derive(Clone, Debug, Default)]
+pub struct VwScene {
+    pub meshes: Vec<VwSceneMesh>,
+    pub materials: Vec<VwMaterial>,
+    pub textures: Vec<VwSceneTexture>,
+    /// Decoded as linear, [`texture_data`](Self::texture_data) switches the ones materials use
+    /// for colors to sRGB.
+    pub images: Vec<VwTextureData>,
+    pub cameras: Vec<VwCamera>,
+    pub nodes: Vec<VwNode>,
+    /// The nodes at the top of the hierarchy. Nodes not reachable from them aren't part of the
+    /// scene.
+    pub roots: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VwSceneMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<VwPrimitive>,
+}
+
+/// An indexed triangle list with a single material.
+#[derive(Clone, Debug, Default)]
+pub struct VwPrimitive {
+    pub vertices: Vec<VwVertex>,
+    pub indices: Vec<u32>,
+    /// `None` uses the default material.
+    pub material: Option<usize>,
+}
+
+impl VwPrimitive {
+    /// The vertices moved to world space by a node's `world_transform`.
+    pub fn world_vertices(&self, world_transform: &VwMat4) -> Vec<VwVertex> {
+        self.vertices
+            .iter()
+            .map(|vertex| {
+                let tangent = vw_math::transform_vector(
+                    world_transform,
+                    [0, 1, 2].map(|i| vertex.tangent[i]),
+                );
+                let tangent = vw_math::normalize(tangent);
+
+                VwVertex {
+                    position: vw_math::transform_point(world_transform, vertex.position),
+                    normal: vw_math::transform_normal(world_transform, vertex.normal),
+                    tangent: [tangent[0], tangent[1], tangent[2], vertex.tangent[3]],
+                    ..*vertex
+                }
+            })
+            .collect()
+    }
+
+    /// Gives every triangle its own vertices with the face normal, for geometry that comes
+    /// without normals. The indices must be in range, which the loaders check.
+    pub fn generate_flat_normals(&mut self) {
+        let mut vertices = Vec::with_capacity(self.indices.len());
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| self.vertices[triangle[corner] as usize]);
+            let normal = vw_math::normalize(vw_math::cross(
+                vw_math::sub(b.position, a.position),
+                vw_math::sub(c.position, a.position),
+            ));
+
+            vertices.extend([a, b, c].map(|vertex| VwVertex { normal, ..vertex }));
+        }
+
+        self.indices = (0..vertices.len() as u32).collect();
+        self.vertices = vertices;
+    }
+
+    /// Gives every vertex the average normal of the triangles around it, weighted by their area.
+    /// Vertices are matched by position, so split UV seams don't show as hard edges. The indices
+    /// must be in range, which the loaders check.
+    pub fn generate_smooth_normals(&mut self) {
+        let position_key = |position: [f32; 3]| position.map(f32::to_bits);
+        let mut normals = HashMap::<[u32; 3], [f32; 3]>::new();
+
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| self.vertices[triangle[corner] as usize]);
+            // The cross product's length is twice the area, which does the weighting
+            let face_normal = vw_math::cross(
+                vw_math::sub(b.position, a.position),
+                vw_math::sub(c.position, a.position),
+            );
+
+            for vertex in [a, b, c] {
+                let normal = normals
+                    .entry(position_key(vertex.position))
+                    .or_insert([0.0; 3]);
+                *normal = [0, 1, 2].map(|axis| normal[axis] + face_normal[axis]);
+            }
+        }
+
+        for vertex in self.vertices.iter_mut() {
+            if let Some(normal) = normals.get(&position_key(vertex.position)) {
+                vertex.normal = vw_math::normalize(*normal);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VwAlphaMode {
+    #[default]
+    Opaque,
+    /// Fragments below `alpha_cutoff` are discarded, the rest are opaque.
+    Mask,
+    Blend,
+}
+
+/// A metallic-roughness material. Factors are linear and multiply their texture. OBJ materials
+/// are converted to it on import.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VwMaterial {
+    pub name: Option<String>,
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<VwTextureRef>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    /// Roughness in the green channel, metalness in blue.
+    pub metallic_roughness_texture: Option<VwTextureRef>,
+    pub normal_texture: Option<VwTextureRef>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<VwTextureRef>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    pub emissive_texture: Option<VwTextureRef>,
+    /// Tints the dielectric specular reflection, as in `KHR_materials_specular`.
+    pub specular_color_factor: [f32; 3],
+    pub specular_texture: Option<VwTextureRef>,
+    pub alpha_mode: VwAlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl Default for VwMaterial {
+    /// The glTF defaults, an opaque, fully metallic and rough white.
+    fn default() -> Self {
+        VwMaterial {
+            name: None,
+            base_color_factor: [1.0; 4],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0; 3],
+            emissive_texture: None,
+            specular_color_factor: [1.0; 3],
+            specular_texture: None,
+            alpha_mode: VwAlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+impl VwMaterial {
+    /// The textures holding colors, which are sRGB encoded, as opposed to data such as normals.
+    pub fn color_textures(&self) -> impl Iterator<Item = &VwTextureRef> {
+        [
+            &self.base_color_texture,
+            &self.emissive_texture,
+            &self.specular_texture,
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+/// A material's use of a texture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VwTextureRef {
+    pub texture: usize,
+    /// Which UV set is sampled. Only set 0 is imported into vertices so far.
+    pub tex_coord: u32,
+    pub transform: VwUvTransform,
+}
+
+/// Offset, rotation and scale applied to UVs before sampling, as in `KHR_texture_transform`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VwUvTransform {
+    pub offset: [f32; 2],
+    /// Counter-clockwise, in radians.
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+impl VwTextureRef {
+    /// Samples UV set 0 without a transform.
+    pub fn new(texture: usize) -> Self {
+        VwTextureRef {
+            texture,
+            tex_coord: 0,
+            transform: VwUvTransform::default(),
+        }
+    }
+}
+
+impl Default for VwUvTransform {
+    fn default() -> Self {
+        VwUvTransform {
+            offset: [0.0; 2],
+            rotation: 0.0,
+            scale: [1.0; 2],
+        }
+    }
+}
+
+impl VwUvTransform {
+    /// The rows of the 2x3 matrix that scales, then rotates, then offsets a UV, in the order
+    /// `KHR_texture_transform` specifies.
+    pub fn matrix(&self) -> [[f32; 3]; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        let [scale_u, scale_v] = self.scale;
+
+        [
+            [cos * scale_u, sin * scale_v, self.offset[0]],
+            [-sin * scale_u, cos * scale_v, self.offset[1]],
+        ]
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VwSceneTexture {
+    pub name: Option<String>,
+    pub image: usize,
+    pub sampler: VwSamplerCreateInfo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VwCamera {
+    Perspective {
+        /// Vertical field of view in radians.
+        yfov: f32,
+        /// The aspect ratio the camera was authored for.
+        aspect_ratio: Option<f32>,
+        znear: f32,
+        /// `None` for an infinite far plane.
+        zfar: Option<f32>,
+    },
+    Orthographic {
+        xmag: f32,
+        ymag: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+impl VwCamera {
+    /// The projection for a viewport with `aspect_ratio`. The viewport's aspect ratio is used
+    /// over the authored one, so the image is never stretched.
+    pub fn projection(&self, aspect_ratio: f32) -> VwMat4 {
+        match *self {
+            VwCamera::Perspective {
+                yfov, znear, zfar, ..
+            } => vw_math::perspective(yfov, aspect_ratio, znear, zfar),
+            VwCamera::Orthographic {
+                ymag, znear, zfar, ..
+            } => vw_math::orthographic(ymag * aspect_ratio, ymag, znear, zfar),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VwNode {
+    pub name: Option<String>,
+    /// Relative to the parent node.
+    pub transform: VwMat4,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    pub camera: Option<usize>,
+}
+
+impl Default for VwNode {
+    fn default() -> Self {
+        VwNode {
+            name: None,
+            transform: vw_math::IDENTITY,
+            children: Vec::new(),
+            mesh: None,
+            camera: None,
+        }
+    }
+}
+
+impl VwScene {
+    /// The world transform of every node, `None` for nodes that aren't reachable from the roots.
+    pub fn world_transforms(&self) -> Vec<Option<VwMat4>> {
+        let mut world_transforms = vec![None; self.nodes.len()];
+        let mut pending = self
+            .roots
+            .iter()
+            .map(|&root| (root, vw_math::IDENTITY))
+            .collect::<Vec<_>>();
+
+        while let Some((node, parent_transform)) = pending.pop() {
+            // A node can only have one parent, this also stops cycles in broken files
+            if world_transforms[node].is_some() {
+                continue;
+            }
+
+            let world_transform = vw_math::mul(&parent_transform, &self.nodes[node].transform);
+            world_transforms[node] = Some(world_transform);
+            pending.extend(
+                self.nodes[node]
+                    .children
+                    .iter()
+                    .map(|&child| (child, world_transform)),
+            );
+        }
+
+        world_transforms
+    }
+
+    /// The pixels of a texture, sRGB encoded when a material samples colors from it.
+    pub fn texture_data(&self, texture: usize) -> VwTextureData {
+        let srgb = self.materials.iter().any(|material| {
+            material
+                .color_textures()
+                .any(|texture_ref| texture_ref.texture == texture)
+        });
+
+        let mut data = self.images[self.textures[texture].image].clone();
+        data.set_srgb(srgb);
+        data
+    }
+
+    /// Replaces everything the renderer draws with this scene. Textures and materials are added
+    /// in order, so the indices in the scene are the renderer's as well, and every primitive
+    /// instance is moved to world space.
+    pub fn upload(&self, renderer: &mut dyn VwRenderer) -> anyhow::Result<()> {
+        renderer.clear_scene()?;
+
+        for (index, texture) in self.textures.iter().enumerate() {
+            log::debug!("Uploading texture {}", name_or_index(&texture.name, index));
+            renderer.add_texture(
+                &self.texture_data(index),
+                &VwTextureCreateInfo {
+                    sampler: texture.sampler,
+                    ..Default::default()
+                },
+            )?;
+        }
+        for material in self.materials.iter() {
+            renderer.add_material(material)?;
+        }
+
+        for (node_index, (node, world_transform)) in
+            self.nodes.iter().zip(self.world_transforms()).enumerate()
+        {
+            let (Some(mesh), Some(world_transform)) = (node.mesh, world_transform) else {
+                continue;
+            };
+
+            log::debug!(
+                "Uploading mesh {} of node {}",
+                name_or_index(&self.meshes[mesh].name, mesh),
+                name_or_index(&node.name, node_index)
+            );
+            for primitive in self.meshes[mesh].primitives.iter() {
+                renderer.add_mesh(
+                    &primitive.world_vertices(&world_transform),
+                    &primitive.indices,
+                    primitive.material,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The world-space bounding box of everything in the scene, `None` when it has no geometry.
+    pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        let mut bounds: Option<([f32; 3], [f32; 3])> = None;
+
+        for (node, world_transform) in self.nodes.iter().zip(self.world_transforms()) {
+            let (Some(mesh), Some(world_transform)) = (node.mesh, world_transform) else {
+                continue;
+            };
+
+            let positions = self.meshes[mesh]
+                .primitives
+                .iter()
+                .flat_map(|primitive| primitive.vertices.iter())
+                .map(|vertex| vw_math::transform_point(&world_transform, vertex.position));
+            for position in positions {
+                let (min, max) = bounds.get_or_insert((position, position));
+                *min = [0, 1, 2].map(|axis| min[axis].min(position[axis]));
+                *max = [0, 1, 2].map(|axis| max[axis].max(position[axis]));
+            }
+        }
+
+        bounds
+    }
+
+    /// The view-projection of the first camera in the scene. Scenes without a camera are viewed
+    /// from the front, far enough back that everything fits.
+    pub fn view_projection(&self, aspect_ratio: f32) -> VwMat4 {
+        let world_transforms = self.world_transforms();
+        let camera_node = self
+            .nodes
+            .iter()
+            .zip(world_transforms)
+            .find_map(|(node, world_transform)| Some((node.camera?, world_transform?)));
+
+        if let Some((camera, world_transform)) = camera_node {
+            let view = vw_math::rigid_inverse(&world_transform);
+            return vw_math::mul(&self.cameras[camera].projection(aspect_ratio), &view);
+        }
+
+        let Some((min, max)) = self.bounds() else {
+            return vw_math::IDENTITY;
+        };
+        let center = [0, 1, 2].map(|axis| (min[axis] + max[axis]) / 2.0);
+        let radius = (vw_math::dot(vw_math::sub(max, min), vw_math::sub(max, min)).sqrt() / 2.0)
+            .max(f32::EPSILON);
+
+        // Far enough for the bounding sphere to fit the narrower of the two fields of view
+        let yfov = 45f32.to_radians();
+        let half_fov = if aspect_ratio < 1.0 {
+            ((yfov / 2.0).tan() * aspect_ratio).atan()
+        } else {
+            yfov / 2.0
+        };
+        let distance = radius / half_fov.sin();
+
+        let eye = [center[0], center[1], center[2] + distance];
+        let view = vw_math::look_at(eye, center, [0.0, 1.0, 0.0]);
+        let projection = vw_math::perspective(
+            yfov,
+            aspect_ratio,
+            (distance - radius).max(distance * 0.001),
+            Some(distance + radius),
+        );
+
+        vw_math::mul(&projection, &view)
+    }
+}
+
+/// Rejects indices past the end of `count` entries, which would make normal generation and
+/// drawing read out of bounds. `what` says which data of which mesh is indexed.
+fn check_indices(indices: &[u32], count: usize, what: &str) -> Result<(), VwError> {
+    match indices.iter().find(|&&index| index as usize >= count) {
+        Some(index) => Err(VwError::InvalidMesh(format!(
+            "{what}: index {index} is out of range for {count} entries"
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// A name for log messages, the index when the file didn't give one.
+fn name_or_index(name: &Option<String>, index: usize) -> String {
+    name.clone().unwrap_or_else(|| format!("#{index}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_indices_accepts_indices_in_range() {
+        assert!(check_indices(&[0, 1, 2, 2, 1, 0], 3, "mesh").is_ok());
+        assert!(check_indices(&[], 0, "mesh").is_ok());
+    }
+
+    #[test]
+    fn check_indices_rejects_index_past_the_end() {
+        let err = check_indices(&[0, 1, 3], 3, "primitive 0 of mesh 2").unwrap_err();
+        assert!(matches!(
+            err,
+            VwError::InvalidMesh(message)
+                if message == "primitive 0 of mesh 2: index 3 is out of range for 3 entries"
+        ));
+    }
+
+    #[test]
+    fn uv_transform_scales_rotates_then_offsets() {
+        let transform = VwUvTransform {
+            offset: [0.5, 0.25],
+            rotation: std::f32::consts::FRAC_PI_2,
+            scale: [2.0, 3.0],
+        };
+        let [row_u, row_v] = transform.matrix();
+        let apply =
+            |uv: [f32; 2]| [row_u, row_v].map(|row| row[0] * uv[0] + row[1] * uv[1] + row[2]);
+
+        // A quarter turn maps U onto -V and V onto U, after scaling
+        let [u, v] = apply([1.0, 0.0]);
+        assert!((u - 0.5).abs() < 1e-6 && (v + 1.75).abs() < 1e-6);
+        let [u, v] = apply([0.0, 1.0]);
+        assert!((u - 3.5).abs() < 1e-6 && (v - 0.25).abs() < 1e-6);
+
+        assert_eq!(
+            VwUvTransform::default().matrix(),
+            [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use std::path::Path;
+
+use gltf::{
+    image::Format,
+    material::AlphaMode,
+    mesh::Mode,
+    texture::{MagFilter, MinFilter, WrappingMode},
+};
+use vulkano::image::sampler::{Filter, SamplerAddressMode, SamplerMipmapMode};
+
+use crate::{
+    vw_engine::{VwSamplerCreateInfo, VwTextureData, VwVertex},
+    vw_error::VwError,
+    vw_scene::{
+        VwAlphaMode, VwCamera, VwMaterial, VwNode, VwPrimitive, VwScene, VwSceneMesh,
+        VwSceneTexture, VwTextureRef, VwUvTransform, check_indices,
+    },
+};
+
+impl VwScene {
+    /// Loads a `.gltf` or `.glb` file, together with its buffers and images whether they are
+    /// embedded or next to the file. Uses the file's default scene, or its first one when there
+    /// is no default.
+    pub fn from_gltf(path: impl AsRef<Path>) -> Result<Self, VwError> {
+        let path = path.as_ref();
+        let (document, buffers, images) = gltf::import(path)?;
+
+        let images = images
+            .iter()
+            .map(|data| VwTextureData::rgba8(data.width, data.height, to_rgba8(data)))
+            .collect();
+        let textures = document
+            .textures()
+            .map(|texture| VwSceneTexture {
+                name: texture.name().map(str::to_string),
+                image: texture.source().index(),
+                sampler: convert_sampler(&texture.sampler()),
+            })
+            .collect();
+        let materials = document.materials().map(convert_material).collect();
+        let cameras = document.cameras().map(convert_camera).collect();
+
+        let meshes = document
+            .meshes()
+            .map(|mesh| {
+                let primitives = mesh
+                    .primitives()
+                    .filter_map(|primitive| {
+                        convert_primitive(&primitive, &buffers, mesh.index()).transpose()
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                Ok(VwSceneMesh {
+                    name: mesh.name().map(str::to_string),
+                    primitives,
+                })
+            })
+            .collect::<Result<_, VwError>>()?;
+
+        let nodes = document
+            .nodes()
+            .map(|node| VwNode {
+                name: node.name().map(str::to_string),
+                transform: node.transform().matrix(),
+                children: node.children().map(|child| child.index()).collect(),
+                mesh: node.mesh().map(|mesh| mesh.index()),
+                camera: node.camera().map(|camera| camera.index()),
+            })
+            .collect();
+        let roots = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .map(|scene| scene.nodes().map(|node| node.index()).collect())
+            .unwrap_or_default();
+
+        let scene = VwScene {
+            meshes,
+            materials,
+            textures,
+            images,
+            cameras,
+            nodes,
+            roots,
+        };
+        log::info!(
+            "Loaded {}: {} meshes, {} materials, {} textures, {} nodes, {} cameras",
+            path.display(),
+            scene.meshes.len(),
+            scene.materials.len(),
+            scene.textures.len(),
+            scene.nodes.len(),
+            scene.cameras.len()
+        );
+
+        Ok(scene)
+    }
+}
+
+/// Reads a primitive into an indexed triangle list. Returns `None` for primitives the engine
+/// can't draw, which are points, lines and anything without positions, and an error for indices
+/// past the end of the vertices. The reader resolves sparse accessors, so their data needs no
+/// special handling here.
+fn convert_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    mesh_index: usize,
+) -> Result<Option<VwPrimitive>, VwError> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()][..]));
+    let Some(positions) = reader.read_positions() else {
+        log::warn!(
+            "Skipping primitive {} of mesh {mesh_index}, it has no positions",
+            primitive.index()
+        );
+        return Ok(None);
+    };
+
+    let mut vertices = positions
+        .map(|position| VwVertex {
+            position,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    let has_normals = match reader.read_normals() {
+        Some(normals) => {
+            vertices
+                .iter_mut()
+                .zip(normals)
+                .for_each(|(vertex, normal)| vertex.normal = normal);
+            true
+        }
+        None => false,
+    };
+    if let Some(uvs) = reader.read_tex_coords(0) {
+        vertices
+            .iter_mut()
+            .zip(uvs.into_f32())
+            .for_each(|(vertex, uv)| vertex.uv = uv);
+    }
+    if let Some(colors) = reader.read_colors(0) {
+        vertices
+            .iter_mut()
+            .zip(colors.into_rgba_f32())
+            .for_each(|(vertex, color)| vertex.color = color);
+    }
+    if let Some(tangents) = reader.read_tangents() {
+        vertices
+            .iter_mut()
+            .zip(tangents)
+            .for_each(|(vertex, tangent)| vertex.tangent = tangent);
+    }
+
+    // Non-indexed primitives draw their vertices in order
+    let indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertices.len() as u32).collect::<Vec<_>>(),
+    };
+    check_indices(
+        &indices,
+        vertices.len(),
+        &format!("primitive {} of mesh {mesh_index}", primitive.index()),
+    )?;
+    let indices = match primitive.mode() {
+        Mode::Triangles => indices,
+        Mode::TriangleStrip => strip_to_list(&indices),
+        Mode::TriangleFan => fan_to_list(&indices),
+        mode => {
+            log::warn!(
+                "Skipping primitive {} of mesh {mesh_index}, {mode:?} isn't supported",
+                primitive.index()
+            );
+            return Ok(None);
+        }
+    };
+    if indices.len() < 3 {
+        return Ok(None);
+    }
+
+    let mut vw_primitive = VwPrimitive {
+        vertices,
+        indices,
+        material: primitive.material().index(),
+    };
+    if !has_normals {
+        vw_primitive.generate_flat_normals();
+    }
+
+    Ok(Some(vw_primitive))
+}
+
+/// Triangle `i` of a strip is `i, i + 1, i + 2`, with the last two swapped on odd triangles to
+/// keep the winding consistent.
+fn strip_to_list(indices: &[u32]) -> Vec<u32> {
+    indices
+        .windows(3)
+        .enumerate()
+        .flat_map(|(i, window)| {
+            if i % 2 == 0 {
+                [window[0], window[1], window[2]]
+            } else {
+                [window[0], window[2], window[1]]
+            }
+        })
+        .collect()
+}
+
+/// Every triangle of a fan shares its first vertex.
+fn fan_to_list(indices: &[u32]) -> Vec<u32> {
+    indices
+        .windows(2)
+        .skip(1)
+        .flat_map(|window| [window[0], window[1], indices[0]])
+        .collect()
+}
+
+fn convert_material(material: gltf::Material) -> VwMaterial {
+    let pbr = material.pbr_metallic_roughness();
+
+    VwMaterial {
+        name: material.name().map(str::to_string),
+        base_color_factor: pbr.base_color_factor(),
+        base_color_texture: pbr.base_color_texture().map(|info| texture_ref(&info)),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .map(|info| texture_ref(&info)),
+        normal_texture: material.normal_texture().map(|normal| VwTextureRef {
+            texture: normal.texture().index(),
+            tex_coord: normal.tex_coord(),
+            transform: VwUvTransform::default(),
+        }),
+        normal_scale: material
+            .normal_texture()
+            .map_or(1.0, |normal| normal.scale()),
+        occlusion_texture: material.occlusion_texture().map(|occlusion| VwTextureRef {
+            texture: occlusion.texture().index(),
+            tex_coord: occlusion.tex_coord(),
+            transform: VwUvTransform::default(),
+        }),
+        occlusion_strength: material
+            .occlusion_texture()
+            .map_or(1.0, |occlusion| occlusion.strength()),
+        emissive_factor: material.emissive_factor(),
+        emissive_texture: material.emissive_texture().map(|info| texture_ref(&info)),
+        specular_color_factor: [1.0; 3],
+        specular_texture: None,
+        alpha_mode: match material.alpha_mode() {
+            AlphaMode::Opaque => VwAlphaMode::Opaque,
+            AlphaMode::Mask => VwAlphaMode::Mask,
+            AlphaMode::Blend => VwAlphaMode::Blend,
+        },
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        double_sided: material.double_sided(),
+    }
+}
+
+/// A texture reference with its `KHR_texture_transform`, which may also override the UV set.
+fn texture_ref(info: &gltf::texture::Info) -> VwTextureRef {
+    let transform = info.texture_transform();
+
+    VwTextureRef {
+        texture: info.texture().index(),
+        tex_coord: transform
+            .as_ref()
+            .and_then(|transform| transform.tex_coord())
+            .unwrap_or(info.tex_coord()),
+        transform: transform
+            .map(|transform| VwUvTransform {
+                offset: transform.offset(),
+                rotation: transform.rotation(),
+                scale: transform.scale(),
+            })
+            .unwrap_or_default(),
+    }
+}
+
+/// glTF leaves filtering to the renderer when a sampler doesn't set it, which is linear here.
+fn convert_sampler(sampler: &gltf::texture::Sampler) -> VwSamplerCreateInfo {
+    let defaults = VwSamplerCreateInfo::default();
+    let (min_filter, mipmap_mode) = match sampler.min_filter() {
+        Some(MinFilter::Nearest | MinFilter::NearestMipmapNearest) => {
+            (Filter::Nearest, SamplerMipmapMode::Nearest)
+        }
+        Some(MinFilter::Linear | MinFilter::LinearMipmapNearest) => {
+            (Filter::Linear, SamplerMipmapMode::Nearest)
+        }
+        Some(MinFilter::NearestMipmapLinear) => (Filter::Nearest, SamplerMipmapMode::Linear),
+        Some(MinFilter::LinearMipmapLinear) | None => (defaults.min_filter, defaults.mipmap_mode),
+    };
+    let address_mode = |wrapping_mode| match wrapping_mode {
+        WrappingMode::ClampToEdge => SamplerAddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => SamplerAddressMode::MirroredRepeat,
+        WrappingMode::Repeat => SamplerAddressMode::Repeat,
+    };
+
+    VwSamplerCreateInfo {
+        mag_filter: match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => Filter::Nearest,
+            Some(MagFilter::Linear) | None => defaults.mag_filter,
+        },
+        min_filter,
+        mipmap_mode,
+        address_mode: [
+            address_mode(sampler.wrap_s()),
+            address_mode(sampler.wrap_t()),
+            SamplerAddressMode::Repeat,
+        ],
+        ..defaults
+    }
+}
+
+fn convert_camera(camera: gltf::Camera) -> VwCamera {
+    match camera.projection() {
+        gltf::camera::Projection::Perspective(perspective) => VwCamera::Perspective {
+            yfov: perspective.yfov(),
+            aspect_ratio: perspective.aspect_ratio(),
+            znear: perspective.znear(),
+            zfar: perspective.zfar(),
+        },
+        gltf::camera::Projection::Orthographic(orthographic) => VwCamera::Orthographic {
+            xmag: orthographic.xmag(),
+            ymag: orthographic.ymag(),
+            znear: orthographic.znear(),
+            zfar: orthographic.zfar(),
+        },
+    }
+}
+
+/// Expands any of the formats glTF images decode to into 8-bit RGBA. Grayscale is replicated
+/// to RGB, 16-bit channels keep their high byte and float channels are clamped to `0..=1`.
+fn to_rgba8(data: &gltf::image::Data) -> Vec<u8> {
+    let (channels, channel_size) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+
+    let read_channel = |bytes: &[u8]| match *bytes {
+        [value] => value,
+        [b0, b1] => (u16::from_ne_bytes([b0, b1]) >> 8) as u8,
+        [b0, b1, b2, b3] => {
+            let value = f32::from_ne_bytes([b0, b1, b2, b3]);
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        }
+        _ => unreachable!("Channels are 1, 2 or 4 bytes"),
+    };
+
+    data.pixels
+        .chunks_exact(channels * channel_size)
+        .flat_map(|pixel| {
+            let mut values = [0; 4];
+            for (value, bytes) in values.iter_mut().zip(pixel.chunks_exact(channel_size)) {
+                *value = read_channel(bytes);
+            }
+
+            match channels {
+                1 => [values[0], values[0], values[0], u8::MAX],
+                2 => [values[0], values[0], values[0], values[1]],
+                3 => [values[0], values[1], values[2], u8::MAX],
+                _ => values,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_keeps_winding_of_odd_triangles() {
+        assert_eq!(strip_to_list(&[0, 1, 2, 3, 4]), [0, 1, 2, 1, 3, 2, 2, 3, 4]);
+    }
+
+    #[test]
+    fn fan_shares_first_vertex() {
+        assert_eq!(fan_to_list(&[0, 1, 2, 3, 4]), [1, 2, 0, 2, 3, 0, 3, 4, 0]);
+    }
+
+    #[test]
+    fn too_short_strips_and_fans_have_no_triangles() {
+        assert!(strip_to_list(&[0, 1]).is_empty());
+        assert!(fan_to_list(&[0, 1]).is_empty());
+        assert!(fan_to_list(&[]).is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    vw_engine::{VwTextureData, VwVertex},
+    vw_error::VwError,
+    vw_scene::{
+        VwAlphaMode, VwMaterial, VwNode, VwPrimitive, VwScene, VwSceneMesh, VwSceneTexture,
+        VwTextureRef, check_indices,
+    },
+};
+
+/// How normals are generated for OBJ geometry that comes without them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VwGeneratedNormals {
+    /// One normal per face, for hard-edged models.
+    Flat,
+    /// Normals averaged across the faces sharing a position.
+    #[default]
+    Smooth,
+}
+
+impl VwScene {
+    /// Loads a Wavefront `.obj` file and the `.mtl` libraries it references. Polygons are
+    /// triangulated, every object becomes a mesh under its own root node, and MTL materials are
+    /// mapped onto [`VwMaterial`]. A missing material library or texture is only a warning.
+    pub fn from_obj(
+        path: impl AsRef<Path>,
+        generated_normals: VwGeneratedNormals,
+    ) -> Result<Self, VwError> {
+        let path = path.as_ref();
+        let load_options = tobj::LoadOptions {
+            triangulate: true,
+            ignore_points: true,
+            ignore_lines: true,
+            ..Default::default()
+        };
+        let (models, obj_materials) = tobj::load_obj(path, &load_options)?;
+
+        let obj_materials = obj_materials.unwrap_or_else(|err| {
+            log::warn!("Using default materials for {}: {err}", path.display());
+            Vec::new()
+        });
+
+        // Texture paths in MTL files are relative to the OBJ file
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        let mut texture_loader = VwObjTextureLoader::default();
+        let materials = obj_materials
+            .iter()
+            .map(|obj_material| convert_material(obj_material, base_dir, &mut texture_loader))
+            .collect();
+
+        let mut scene = VwScene {
+            materials,
+            textures: texture_loader.textures,
+            images: texture_loader.images,
+            ..Default::default()
+        };
+
+        for model in models {
+            let Some(mut primitive) = convert_mesh(&model.mesh, &model.name)? else {
+                continue;
+            };
+            if model.mesh.normals.is_empty() {
+                match generated_normals {
+                    VwGeneratedNormals::Flat => primitive.generate_flat_normals(),
+                    VwGeneratedNormals::Smooth => primitive.generate_smooth_normals(),
+                }
+            }
+
+            scene.roots.push(scene.nodes.len());
+            scene.nodes.push(VwNode {
+                name: Some(model.name.clone()),
+                mesh: Some(scene.meshes.len()),
+                ..Default::default()
+            });
+            scene.meshes.push(VwSceneMesh {
+                name: Some(model.name),
+                primitives: vec![primitive],
+            });
+        }
+
+        log::info!(
+            "Loaded {}: {} meshes, {} materials, {} textures",
+            path.display(),
+            scene.meshes.len(),
+            scene.materials.len(),
+            scene.textures.len()
+        );
+
+        Ok(scene)
+    }
+}
+
+/// Turns a triangulated OBJ mesh into an indexed primitive. OBJ indexes positions, UVs and
+/// normals separately, so every distinct combination becomes one vertex. Fails when an index is
+/// past the end of the data it indexes.
+fn convert_mesh(mesh: &tobj::Mesh, name: &str) -> Result<Option<VwPrimitive>, VwError> {
+    if mesh.indices.is_empty() {
+        return Ok(None);
+    }
+
+    check_indices(
+        &mesh.indices,
+        mesh.positions.len() / 3,
+        &format!("positions of object {name}"),
+    )?;
+    check_indices(
+        &mesh.texcoord_indices,
+        mesh.texcoords.len() / 2,
+        &format!("UVs of object {name}"),
+    )?;
+    check_indices(
+        &mesh.normal_indices,
+        mesh.normals.len() / 3,
+        &format!("normals of object {name}"),
+    )?;
+    if !mesh.vertex_color.is_empty() {
+        check_indices(
+            &mesh.indices,
+            mesh.vertex_color.len() / 3,
+            &format!("vertex colors of object {name}"),
+        )?;
+    }
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::with_capacity(mesh.indices.len());
+    let mut unique_vertices = HashMap::new();
+
+    for (corner, &position_index) in mesh.indices.iter().enumerate() {
+        let uv_index = mesh.texcoord_indices.get(corner).copied();
+        let normal_index = mesh.normal_indices.get(corner).copied();
+
+        let index = *unique_vertices
+            .entry((position_index, uv_index, normal_index))
+            .or_insert_with(|| {
+                vertices.push(obj_vertex(mesh, position_index, uv_index, normal_index));
+                vertices.len() as u32 - 1
+            });
+        indices.push(index);
+    }
+
+    Ok(Some(VwPrimitive {
+        vertices,
+        indices,
+        material: mesh.material_id,
+    }))
+}
+
+fn obj_vertex(
+    mesh: &tobj::Mesh,
+    position_index: u32,
+    uv_index: Option<u32>,
+    normal_index: Option<u32>,
+) -> VwVertex {
+    let vec3 = |data: &[f32], index: u32| {
+        let start = index as usize * 3;
+        [data[start], data[start + 1], data[start + 2]]
+    };
+
+    let mut vertex = VwVertex {
+        position: vec3(&mesh.positions, position_index),
+        ..Default::default()
+    };
+    if let Some(normal_index) = normal_index {
+        vertex.normal = vec3(&mesh.normals, normal_index);
+    }
+    if let Some(uv_index) = uv_index {
+        let start = uv_index as usize * 2;
+        // OBJ puts V = 0 at the bottom of the image, Vulkan at the top
+        vertex.uv = [mesh.texcoords[start], 1.0 - mesh.texcoords[start + 1]];
+    }
+    // Vertex colors are a common extension and share the position's index
+    if !mesh.vertex_color.is_empty() {
+        let [r, g, b] = vec3(&mesh.vertex_color, position_index);
+        vertex.color = [r, g, b, 1.0];
+    }
+
+    vertex
+}
+
+/// Maps an MTL material onto the metallic-roughness model. MTL has no metalness, so every
+/// material is a dielectric, and the Phong exponent is converted to a roughness.
+fn convert_material(
+    obj_material: &tobj::Material,
+    base_dir: &Path,
+    texture_loader: &mut VwObjTextureLoader,
+) -> VwMaterial {
+    let [r, g, b] = obj_material.diffuse.unwrap_or([1.0; 3]);
+    let alpha = obj_material.dissolve.unwrap_or(1.0);
+
+    let ignored_textures = [
+        ("ambient", &obj_material.ambient_texture),
+        ("shininess", &obj_material.shininess_texture),
+        ("dissolve", &obj_material.dissolve_texture),
+    ];
+    for (what, name) in ignored_textures {
+        if let Some(name) = name {
+            log::warn!(
+                "Ignoring the {what} texture {name} of material {}, it has no counterpart",
+                obj_material.name
+            );
+        }
+    }
+
+    let mut texture = |name: &Option<String>| {
+        name.as_deref()
+            .and_then(|name| texture_loader.load(base_dir, name))
+            .map(VwTextureRef::new)
+    };
+
+    VwMaterial {
+        name: Some(obj_material.name.clone()),
+        base_color_factor: [r, g, b, alpha],
+        base_color_texture: texture(&obj_material.diffuse_texture),
+        metallic_factor: 0.0,
+        roughness_factor: obj_material
+            .shininess
+            .map_or(1.0, |shininess| (2.0 / (shininess.max(0.0) + 2.0)).sqrt()),
+        // Most exporters write tangent-space normal maps to `map_Bump`
+        normal_texture: texture(&obj_material.normal_texture),
+        emissive_factor: obj_material.emissive.unwrap_or([0.0; 3]),
+        specular_color_factor: obj_material.specular.unwrap_or([1.0; 3]),
+        specular_texture: texture(&obj_material.specular_texture),
+        alpha_mode: if alpha < 1.0 {
+            VwAlphaMode::Blend
+        } else {
+            VwAlphaMode::Opaque
+        },
+        ..Default::default()
+    }
+}
+
+/// Decodes every texture file once, however many materials reference it.
+#[derive(Default)]
+struct VwObjTextureLoader {
+    textures: Vec<VwSceneTexture>,
+    images: Vec<VwTextureData>,
+    loaded: HashMap<String, Option<usize>>,
+}
+
+impl VwObjTextureLoader {
+    /// The texture index for `name`, or `None` when the file can't be decoded. Images are
+    /// decoded as linear, the scene decides which ones hold sRGB colors.
+    fn load(&mut self, base_dir: &Path, name: &str) -> Option<usize> {
+        if let Some(texture) = self.loaded.get(name) {
+            return *texture;
+        }
+
+        let texture = match VwTextureData::load(base_dir.join(name), false) {
+            Ok(image) => {
+                self.textures.push(VwSceneTexture {
+                    name: Some(name.to_string()),
+                    image: self.images.len(),
+                    ..Default::default()
+                });
+                self.images.push(image);
+                Some(self.textures.len() - 1)
+            }
+            Err(err) => {
+                log::warn!("Skipping texture {name}: {err}");
+                None
+            }
+        };
+
+        self.loaded.insert(name.to_string(), texture);
+        texture
+    }
+}
@@ -6,12 +6,15 @@ use vulkano::{
     swapchain::{Surface, SurfaceApi},
 };
 
+use crate::vw_error::VwError;
+
 pub struct VwWindow {
     sdl_context: sdl2::Sdl,
     // sdl_video: sdl2::VideoSubsystem,
     sdl_window: sdl2::video::Window,
 }
 
+#[derive(Clone)]
 pub struct VwWindowCreateInfo<'s> {
     pub title: &'s str,
     pub extent: (u32, u32),
@@ -20,11 +23,9 @@ pub struct VwWindowCreateInfo<'s> {
 }
 
 impl VwWindow {
-    pub fn new(create_info: VwWindowCreateInfo) -> Self {
-        let sdl_context = sdl2::init().expect("Failed to initialize SDL2");
-        let sdl_video = sdl_context
-            .video()
-            .expect("Failed to get SDL2 video subsystem");
+    pub fn new(create_info: VwWindowCreateInfo) -> Result<Self, VwError> {
+        let sdl_context = sdl2::init().map_err(VwError::Sdl)?;
+        let sdl_video = sdl_context.video().map_err(VwError::Sdl)?;
 
         let sdl_window = {
             let title = if cfg!(debug_assertions) {
@@ -43,37 +44,80 @@ impl VwWindow {
             }
             window_builder
                 .build()
-                .expect("Failed to create SDL2 window")
+                .map_err(|err| VwError::Sdl(err.to_string()))?
         };
 
-        VwWindow {
+        Ok(VwWindow {
             sdl_context,
             // sdl_video,
             sdl_window,
-        }
+        })
+    }
+
+    /// Instance extensions SDL needs to create a surface for this window on the current
+    /// windowing system.
+    pub fn required_instance_extensions(&self) -> Result<Vec<&'static str>, VwError> {
+        self.sdl_window
+            .vulkan_instance_extensions()
+            .map_err(VwError::Sdl)
     }
 
-    pub fn present(&self) {
-        self.sdl_window.gl_swap_window();
+    pub fn create_vk_surface(&self, instance: Arc<Instance>) -> Result<Arc<Surface>, VwError> {
+        let surface_api = self.surface_api()?;
+        let surface_khr = self.create_raw_surface(instance.handle())?;
+
+        let surface = unsafe { Surface::from_handle(instance, surface_khr, surface_api, None) };
+        Ok(Arc::new(surface))
     }
 
-    pub fn create_vk_surface(&self, instance: Arc<Instance>) -> Arc<Surface> {
+    /// Creates a surface for an instance that isn't managed by vulkano. The caller owns the
+    /// returned handle and has to destroy it before the instance.
+    pub fn create_raw_surface(
+        &self,
+        instance: ash::vk::Instance,
+    ) -> Result<ash::vk::SurfaceKHR, VwError> {
         let handle = self
             .sdl_window
-            .vulkan_create_surface(instance.handle().as_raw() as _)
-            .expect("Failed to create Vulkan surface");
+            .vulkan_create_surface(instance.as_raw() as _)
+            .map_err(VwError::SurfaceCreation)?;
 
-        let surface_khr = ash::vk::SurfaceKHR::from_raw(handle);
+        Ok(ash::vk::SurfaceKHR::from_raw(handle))
+    }
+
+    /// The surface API matching the platform extension SDL asks for. SDL only requests Xcb when
+    /// Xlib surfaces aren't available, so checking them in this order mirrors its own choice.
+    fn surface_api(&self) -> Result<SurfaceApi, VwError> {
+        let extensions = self.required_instance_extensions()?;
+        let apis = [
+            ("VK_KHR_win32_surface", SurfaceApi::Win32),
+            ("VK_KHR_xlib_surface", SurfaceApi::Xlib),
+            ("VK_KHR_xcb_surface", SurfaceApi::Xcb),
+            ("VK_KHR_wayland_surface", SurfaceApi::Wayland),
+            ("VK_EXT_metal_surface", SurfaceApi::Metal),
+            ("VK_MVK_macos_surface", SurfaceApi::MacOs),
+            ("VK_KHR_android_surface", SurfaceApi::Android),
+        ];
+
+        apis.into_iter()
+            .find_map(|(name, api)| extensions.contains(&name).then_some(api))
+            .ok_or_else(|| {
+                VwError::SurfaceCreation("SDL2 requested no known surface extension".into())
+            })
+    }
+
+    /// Size of the window's drawable in pixels, which can differ from the window size on
+    /// high-DPI displays.
+    pub fn drawable_extent(&self) -> [u32; 2] {
+        let (width, height) = self.sdl_window.vulkan_drawable_size();
+        [width, height]
+    }
 
-        let surface =
-            unsafe { Surface::from_handle(instance, surface_khr, SurfaceApi::Win32, None) };
-        Arc::new(surface)
+    pub fn is_minimized(&self) -> bool {
+        self.sdl_window.is_minimized()
     }
 
-    pub fn event_pump(&self) -> sdl2::EventPump {
-        self.sdl_context
-            .event_pump()
-            .expect("Failed to get SDL2 event pump")
+    pub fn event_pump(&self) -> Result<sdl2::EventPump, VwError> {
+        self.sdl_context.event_pump().map_err(VwError::Sdl)
     }
 
     pub fn set_relative_mouse_mode(&self, enabled: bool) {